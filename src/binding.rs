@@ -0,0 +1,192 @@
+use std::time::{Duration, Instant};
+use crate::config::ButtonBindings;
+use crate::direction::Direction;
+use crate::MfdState;
+
+// A button that fires this many times this quickly is an axis or a loose contact, not a person
+const NOISE_PRESSES: usize = 4;
+const NOISE_WINDOW: Duration = Duration::from_millis(500);
+// ...and a confirming press any sooner than this after the first is more likely to be noise too
+const CONFIRM_DELAY: Duration = Duration::from_millis(150);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BindStep {
+    Hat(Direction),
+    Confirm,   // Cursor mode only
+    Modifier,
+}
+
+impl BindStep {
+    pub fn label(&self) -> String {
+        match self {
+            BindStep::Hat(direction) => format!("{:?}", direction),
+            BindStep::Confirm => "Confirm".to_string(),
+            BindStep::Modifier => "Shift".to_string(),
+        }
+    }
+
+    // Optional steps are skipped by pressing one of the hat buttons
+    pub fn is_optional(&self) -> bool {
+        !matches!(self, BindStep::Hat(_))
+    }
+}
+
+// Bindings are collected here and only handed back once every step is done, so
+// backing out leaves the config as it was
+#[derive(Debug)]
+pub struct BindingWizard {
+    pub mfd: MfdState,             // Where to go back to when done or cancelled
+    pub bindings: ButtonBindings,
+    steps: Vec<BindStep>,
+    step: usize,
+    pub pending: Option<((u32, u32), String)>,  // Detected button and its description, waiting to be confirmed
+    pub detected_at: Instant,
+    pub message: Option<String>,                // Why the last press was turned down
+    presses: Vec<((u32, u32), Instant)>,
+    noisy: Vec<(u32, u32)>,
+    held: Vec<(u32, u32)>,  // So held keys repeating don't count as presses; keyboard keys only get here when releases are reported
+}
+
+impl BindingWizard {
+    pub fn new(mfd: MfdState, bindings: ButtonBindings, cursor_mode: bool, shift: bool) -> Self {
+        let mut steps: Vec<BindStep> = [Direction::Up, Direction::Right, Direction::Down, Direction::Left]
+            .into_iter()
+            .map(BindStep::Hat)
+            .collect();
+        if cursor_mode {
+            steps.push(BindStep::Confirm);
+        }
+        if shift {
+            steps.push(BindStep::Modifier);
+        }
+        BindingWizard {
+            mfd,
+            bindings,
+            steps,
+            step: 0,
+            pending: None,
+            detected_at: Instant::now(),
+            message: None,
+            presses: Vec::new(),
+            noisy: Vec::new(),
+            held: Vec::new(),
+        }
+    }
+
+    pub fn step(&self) -> BindStep {
+        self.steps[self.step]
+    }
+
+    // 1-based, for the status line
+    pub fn progress(&self) -> (usize, usize) {
+        (self.step + 1, self.steps.len())
+    }
+
+    // The first press of a button picks it and a second press confirms it. `taken` says
+    // what else the button is already used for outside the hat, if anything.
+    // Returns true once the last step is confirmed.
+    pub fn press(&mut self, button: (u32, u32), label: String, taken: Option<String>, now: Instant) -> bool {
+        if self.held.contains(&button) {
+            return false;
+        }
+        self.held.push(button);
+
+        self.presses.retain(|(_, at)| now.duration_since(*at) < NOISE_WINDOW);
+        self.presses.push((button, now));
+        if self.presses.iter().filter(|(pressed, _)| *pressed == button).count() >= NOISE_PRESSES
+            && !self.noisy.contains(&button) {
+            self.noisy.push(button);
+        }
+        if self.noisy.contains(&button) {
+            if self.pending.as_ref().is_some_and(|(pending, _)| *pending == button) {
+                self.pending = None;
+            }
+            self.message = Some(format!("Ignoring {} - it keeps firing on its own", label));
+            return false;
+        }
+
+        let step = self.step();
+        let hat_step = self.steps[..self.step].iter().position(|earlier| self.bound(*earlier) == Some(button));
+
+        if step.is_optional() && hat_step.is_some_and(|index| matches!(self.steps[index], BindStep::Hat(_))) {
+            self.set(step, None);
+            return self.advance();
+        }
+
+        if let Some(index) = hat_step {
+            self.pending = None;
+            self.message = Some(format!("{} is already bound to {}", label, self.steps[index].label()));
+            return false;
+        }
+        if let Some(other) = taken {
+            self.pending = None;
+            self.message = Some(format!("{} is already used for {}", label, other));
+            return false;
+        }
+
+        match &self.pending {
+            Some((pending, _)) if *pending == button => {
+                if now.duration_since(self.detected_at) < CONFIRM_DELAY {
+                    return false;
+                }
+                self.set(step, Some(button));
+                self.advance()
+            }
+            _ => {
+                self.pending = Some((button, label));
+                self.detected_at = now;
+                self.message = None;
+                false
+            }
+        }
+    }
+
+    pub fn release(&mut self, button: (u32, u32)) {
+        self.held.retain(|held| *held != button);
+    }
+
+    // Drop the detected button, or go back to the previous step if there isn't one
+    pub fn back(&mut self) {
+        if self.pending.take().is_none() && self.step > 0 {
+            self.step -= 1;
+        }
+        self.message = None;
+    }
+
+    fn advance(&mut self) -> bool {
+        self.pending = None;
+        self.message = None;
+        if self.step + 1 == self.steps.len() {
+            return true;
+        }
+        self.step += 1;
+        false
+    }
+
+    fn bound(&self, step: BindStep) -> Option<(u32, u32)> {
+        match step {
+            BindStep::Hat(Direction::Up) => Some(self.bindings.up),
+            BindStep::Hat(Direction::Right) => Some(self.bindings.right),
+            BindStep::Hat(Direction::Down) => Some(self.bindings.down),
+            BindStep::Hat(Direction::Left) => Some(self.bindings.left),
+            BindStep::Confirm => self.bindings.confirm,
+            BindStep::Modifier => self.bindings.modifier,
+        }
+    }
+
+    fn set(&mut self, step: BindStep, button: Option<(u32, u32)>) {
+        match step {
+            BindStep::Hat(direction) => {
+                let button = button.unwrap_or((0, 0));
+                match direction {
+                    Direction::Up => self.bindings.up = button,
+                    Direction::Right => self.bindings.right = button,
+                    Direction::Down => self.bindings.down = button,
+                    Direction::Left => self.bindings.left = button,
+                }
+            }
+            BindStep::Confirm => self.bindings.confirm = button,
+            BindStep::Modifier => self.bindings.modifier = button,
+        }
+    }
+}
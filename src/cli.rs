@@ -0,0 +1,264 @@
+use gilrs::Gilrs;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::{self, Config, OutputMode, DEFAULT_PROFILE};
+use crate::keyfiles;
+use crate::midi;
+use crate::output;
+use crate::profile::OsbSettings;
+use crate::MfdState;
+
+pub const USAGE: &str = "\
+Usage: superhat [options] [command]
+
+Commands:
+  run                         Start Superhat (the default)
+  bind                        Start Superhat in the binding wizard
+  list-devices                List the controllers and MIDI ports Superhat can see
+  show-config                 Print where the config is and what's in it
+  validate-profile [name]     Check the key chords in a profile, or in every profile
+  import-bms-keys <file>      Take the OSB keys from a BMS .key file
+  export-dcs [file]           Write the OSB keys as a DCS F-16C keyboard diff.lua
+  press <left|right> <osb>    Press an OSB (1-20) on one MFD
+  help                        Show this
+
+Options:
+  --config <path>             Use this config file
+  --profile <name>            Use this profile
+  --output <keyboard|joystick>  Send OSBs this way, for this run only
+  --headless                  Run without the console window";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run,
+    Bind,
+    ListDevices,
+    ShowConfig,
+    ValidateProfile(Option<String>),
+    ImportBmsKeys(PathBuf),
+    ExportDcs(Option<PathBuf>),
+    Press(u8),  // OSB 1-40
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    pub config: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub output: Option<OutputMode>,
+    pub headless: bool,
+}
+
+// Options can go before or after the command
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options { command: Command::Run, config: None, profile: None, output: None, headless: false };
+    let mut words = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--config" => options.config = Some(value()?.into()),
+            "--profile" => options.profile = Some(value()?),
+            "--output" => {
+                options.output = Some(match value()?.to_ascii_lowercase().as_str() {
+                    "keyboard" => OutputMode::Keyboard,
+                    "joystick" => OutputMode::Joystick,
+                    other => return Err(format!("Unknown output '{}', expected keyboard or joystick", other)),
+                })
+            }
+            "--headless" => options.headless = true,
+            "-h" | "--help" => options.command = Command::Help,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+            _ => words.push(arg),
+        }
+    }
+    if options.command == Command::Help {
+        return Ok(options);
+    }
+
+    let mut words = words.into_iter();
+    let command = words.next();
+    options.command = match command.as_deref() {
+        None | Some("run") => Command::Run,
+        Some("bind") => Command::Bind,
+        Some("list-devices") => Command::ListDevices,
+        Some("show-config") => Command::ShowConfig,
+        Some("validate-profile") => Command::ValidateProfile(words.next()),
+        Some("import-bms-keys") => {
+            Command::ImportBmsKeys(words.next().ok_or("import-bms-keys needs a .key file")?.into())
+        }
+        Some("export-dcs") => Command::ExportDcs(words.next().map(PathBuf::from)),
+        Some("press") => Command::Press(parse_press(&mut words)?),
+        Some("help") => Command::Help,
+        Some(other) => return Err(format!("Unknown command '{}'", other)),
+    };
+    if let Some(extra) = words.next() {
+        return Err(format!("Unexpected argument '{}'", extra));
+    }
+    Ok(options)
+}
+
+// "left 3" or "right 20", as the OSB number 1-40. Shared with the control socket.
+pub fn parse_press<S: AsRef<str>>(words: &mut impl Iterator<Item = S>) -> Result<u8, String> {
+    let usage = "press needs an MFD and an OSB, e.g. press left 3";
+    let mfd = match words.next().ok_or(usage)?.as_ref().to_ascii_lowercase().as_str() {
+        "left" => MfdState::LeftMfd,
+        "right" => MfdState::RightMfd,
+        _ => return Err(usage.to_string()),
+    };
+    let osb: u8 = words.next().and_then(|osb| osb.as_ref().parse().ok())
+        .filter(|osb| (1..=20).contains(osb))
+        .ok_or("OSBs go from 1 to 20 on each MFD")?;
+    Ok(osb + mfd.index() as u8 * 20)
+}
+
+// Make the named profile the active one, "Default" being the main settings
+pub fn select_profile(config: &mut Config, name: &str) -> Result<(), String> {
+    if name.eq_ignore_ascii_case(DEFAULT_PROFILE) {
+        config.active_profile = None;
+        return Ok(());
+    }
+    match config.profiles.iter().find(|profile| profile.name.eq_ignore_ascii_case(name)) {
+        Some(profile) => {
+            config.active_profile = Some(profile.name.clone());
+            Ok(())
+        }
+        None => Err(format!("There's no profile called '{}'", name)),
+    }
+}
+
+// The config for a one-off command, with --profile applied
+fn load(options: &Options) -> Result<Config, String> {
+    let mut config = config::load_config()?;
+    if let Some(name) = &options.profile {
+        select_profile(&mut config, name)?;
+    }
+    Ok(config)
+}
+
+// Everything but run and bind, which are the main loop's
+pub fn run_command(options: &Options) -> Result<(), String> {
+    match &options.command {
+        Command::Run | Command::Bind => Ok(()),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Command::ListDevices => list_devices(),
+        Command::ShowConfig => show_config(options),
+        Command::ValidateProfile(name) => validate_profile(options, name.as_deref()),
+        Command::ImportBmsKeys(path) => import_bms_keys(options, path),
+        Command::ExportDcs(path) => export_dcs(options, path.as_deref()),
+        Command::Press(osb) => press(options, *osb),
+    }
+}
+
+fn list_devices() -> Result<(), String> {
+    let gilrs = Gilrs::new().map_err(|err| format!("Couldn't look for controllers: {}", err))?;
+    println!("Controllers:");
+    for (id, gamepad) in gilrs.gamepads() {
+        println!("  {}: {}", usize::from(id), gamepad.name());
+    }
+    // midi_device in the config is the number on the left
+    println!("MIDI ports:");
+    for (index, name) in midi::port_names().iter().enumerate() {
+        println!("  {}: {}", index, name);
+    }
+    Ok(())
+}
+
+fn show_config(options: &Options) -> Result<(), String> {
+    let config = load(options)?;
+    println!("# {}", config::config_path().display());
+    println!("# Profile: {}", config.profile_name());
+    print!("{}", toml::to_string(&config).map_err(|err| err.to_string())?);
+    Ok(())
+}
+
+fn validate_profile(options: &Options, name: Option<&str>) -> Result<(), String> {
+    let mut config = load(options)?;
+    let names = match name {
+        Some(name) => {
+            select_profile(&mut config, name)?;
+            vec![config.active_profile.clone()]
+        }
+        None => std::iter::once(None).chain(config.profiles.iter().map(|profile| Some(profile.name.clone()))).collect(),
+    };
+
+    let mut failed = false;
+    for name in names {
+        config.active_profile = name;
+        let problems = config.key_profile().problems();
+        if problems.is_empty() {
+            println!("{}: ok", config.profile_name());
+        } else {
+            failed = true;
+            println!("{}:", config.profile_name());
+            for problem in problems {
+                println!("  {}", problem);
+            }
+        }
+    }
+    if failed { Err("Some profiles have problems".to_string()) } else { Ok(()) }
+}
+
+// Chords that match the BMS defaults are dropped, so a stock key file leaves the profile clean.
+// --profile only picks where the keys go; the saved config keeps its active profile.
+fn import_bms_keys(options: &Options, path: &Path) -> Result<(), String> {
+    let mut config = config::load_config()?;
+    let active_profile = config.active_profile.clone();
+    if let Some(name) = &options.profile {
+        select_profile(&mut config, name)?;
+    }
+    let text = fs::read(path).map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+    let (chords, skipped) = keyfiles::parse_bms_keys(&String::from_utf8_lossy(&text));
+    if chords.is_empty() {
+        return Err(format!("No OSB keys in {}", path.display()));
+    }
+
+    let key_profile = config.key_profile_mut();
+    for (osb_number, chord) in &chords {
+        let chord = Some(chord.clone()).filter(|chord| chord != crate::mfd_keys::MFD_KEYS[*osb_number as usize - 1]);
+        match key_profile.osb.iter_mut().find(|settings| settings.osb == *osb_number) {
+            Some(settings) => settings.chord = chord,
+            None if chord.is_some() => key_profile.osb.push(OsbSettings { osb: *osb_number, chord, ..Default::default() }),
+            None => {}
+        }
+    }
+    let profile_name = config.profile_name().to_string();
+    config.active_profile = active_profile;
+    config::try_save_config(&config).map_err(|err| format!("Couldn't save {}: {}", config::config_path().display(), err))?;
+
+    println!("Imported {} OSB keys into profile {}", chords.len(), profile_name);
+    for problem in skipped {
+        println!("  Skipped {}", problem);
+    }
+    Ok(())
+}
+
+fn export_dcs(options: &Options, path: Option<&Path>) -> Result<(), String> {
+    let config = load(options)?;
+    let lua = keyfiles::dcs_keyboard_diff(|osb| config.key_profile().osb_chord(osb).map(str::to_string));
+    match path {
+        Some(path) => fs::write(path, lua).map_err(|err| format!("Couldn't write {}: {}", path.display(), err)),
+        None => {
+            print!("{}", lua);
+            Ok(())
+        }
+    }
+}
+
+// A tap, long enough for the sim to see it
+fn press(options: &Options, osb: u8) -> Result<(), String> {
+    let config = load(options)?;
+    let output_mode = options.output.unwrap_or(config.output_mode);
+    *config::CONFIG.lock().unwrap() = Some(config);
+    output::init(output_mode).map_err(|err| format!("Couldn't set up the output: {}", err))?;
+    output::press_osb(osb);
+    std::thread::sleep(Duration::from_millis(100));
+    output::release_osb(osb);
+    Ok(())
+}
@@ -1,52 +1,444 @@
 use serde::{Serialize, Deserialize};
+use std::env;
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
+use std::time::SystemTime;
 use crate::MfdState;
+use crate::profile::KeyProfile;
+use crate::midi::MidiMapping;
+use crate::sound::SoundSettings;
 
+// Bump this and add to MIGRATIONS when the layout changes in a way defaults can't cover
+pub const CONFIG_VERSION: u32 = 1;
+
+// Keys missing from the file take their value from Config::default()
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    pub version: u32,
     pub button_bindings: ButtonBindings,
     pub selected_mfd: MfdState,
     pub sound_enabled: bool,
+    pub output_mode: OutputMode,
+    pub selection_mode: SelectionMode,
+    pub cursor_positions: [u8; 2],  // Last cursor index on the (left, right) MFD
+    pub repeat_gesture: RepeatGesture,
+    pub profile: KeyProfile,
+    pub shift_layer: Option<ShiftLayer>,  // Without one there's no Shift button to bind
+    pub keypad_long_press: bool,  // Long pressing towards the selected MFD opens the ICP keypad
+    pub profile_long_press: bool,  // Long pressing up with no OSB started moves on to the next profile
+    pub extra_hats: Vec<HatBindings>,
+    pub midi_device: Option<u32>,  // MIDI input port to listen on, if any
+    pub midi: Vec<MidiMapping>,
+    pub direct_buttons: Vec<DirectButton>,
+    pub profiles: Vec<NamedProfile>,
+    pub active_profile: Option<String>,  // One of the profiles by name, or the settings above if none
+    pub telemetry_port: Option<u16>,     // UDP port the sim sends the aircraft name to, if any
+    pub control_port: Option<u16>,       // Local TCP port to take requests on, if any
+    pub sounds: SoundSettings,
+}
+
+// How OSB presses are sent to the sim
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum OutputMode {
+    #[default]
+    Keyboard,   // Key chords from mfd_keys
+    Joystick,   // One virtual joystick button per OSB
+}
+
+// Which hat grammar picks the OSB once a side has been chosen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum SelectionMode {
+    #[default]
+    Edge,       // Move along the edge, confirm with the side direction
+    Direct,     // Fixed one or two press code per OSB
+    Cursor,     // Roam a cursor around the bezel and confirm
+}
+
+// Shortcut for pressing the last OSB again
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum RepeatGesture {
+    #[default]
+    Off,
+    DoubleTap,  // Double-tap the side of the last OSB; the middle OSB there needs a slower second press
+}
+
+// What the hat drives while the modifier button is held
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ShiftLayer {
+    Keypad,
+    Macros,  // One key chord per hat direction, from the profile
 }
 
+// A hat on top of the main one, always driving the same display
 #[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HatBindings {
+    pub up: (u32, u32),    // (device_id, button_code)
+    pub right: (u32, u32),
+    pub down: (u32, u32),
+    pub left: (u32, u32),
+    pub role: HatRole,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HatRole {
+    LeftMfd,
+    RightMfd,
+    Keypad,
+}
+
+// What a button bound straight to the MFDs does, skipping the hat
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ButtonAction {
+    Osb(u8),            // Press this OSB (1-40) for as long as the button is held
+    SelectMfd(MfdState),
+    Macro(String),      // Key chord, held for as long as the button is
+}
+
+// A button box button mapped one-to-one, e.g. { button = [2, 5], action = { Osb = 1 } }
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DirectButton {
+    #[serde(default)]
+    pub button: Option<(u32, u32)>,  // (device_id, button_code), learned from the next unbound press if left out
+    pub action: ButtonAction,
+}
+
+// Bindings and a key table for one sim or aircraft, e.g. [[profiles]] name = "DCS F-16".
+// The first one whose process is running or aircraft is reported gets picked automatically.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NamedProfile {
+    pub name: String,
+    pub processes: Vec<String>,  // Executable names, e.g. "DCS.exe"
+    pub aircraft: Vec<String>,   // As the telemetry feed names them, e.g. "F-16C_50"
+    pub button_bindings: Option<ButtonBindings>,  // The main bindings if left out
+    pub profile: KeyProfile,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ButtonBindings {
     pub up: (u32, u32),    // (device_id, button_code)
     pub right: (u32, u32),
     pub down: (u32, u32),
     pub left: (u32, u32),
+    #[serde(default)]
+    pub confirm: Option<(u32, u32)>,  // Cursor mode only; long press is used if unbound
+    #[serde(default)]
+    pub modifier: Option<(u32, u32)>,  // Held to switch the hat to the shift layer
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
+            version: CONFIG_VERSION,
             button_bindings: ButtonBindings {
                 up: (0, 0),    // Invalid binding
                 right: (0, 0), // Invalid binding
                 down: (0, 0),  // Invalid binding
                 left: (0, 0),  // Invalid binding
+                confirm: None,
+                modifier: None,
             },
             selected_mfd: MfdState::LeftMfd,
             sound_enabled: true,
+            output_mode: OutputMode::Keyboard,
+            selection_mode: SelectionMode::Edge,
+            cursor_positions: [0, 0],
+            repeat_gesture: RepeatGesture::Off,
+            profile: KeyProfile::default(),
+            shift_layer: None,
+            keypad_long_press: false,
+            profile_long_press: false,
+            extra_hats: Vec::new(),
+            midi_device: None,
+            midi: Vec::new(),
+            direct_buttons: Vec::new(),
+            profiles: Vec::new(),
+            active_profile: None,
+            telemetry_port: None,
+            control_port: None,
+            sounds: SoundSettings::default(),
+        }
+    }
+}
+
+// The settings at the top level act as a profile of their own, under this name
+pub const DEFAULT_PROFILE: &str = "Default";
+
+impl Config {
+    fn named_profile(&self) -> Option<&NamedProfile> {
+        let name = self.active_profile.as_ref()?;
+        self.profiles.iter().find(|profile| profile.name == *name)
+    }
+
+    pub fn profile_name(&self) -> &str {
+        self.named_profile().map_or(DEFAULT_PROFILE, |profile| profile.name.as_str())
+    }
+
+    pub fn key_profile(&self) -> &KeyProfile {
+        self.named_profile().map_or(&self.profile, |profile| &profile.profile)
+    }
+
+    pub fn key_profile_mut(&mut self) -> &mut KeyProfile {
+        let name = self.active_profile.clone();
+        match self.profiles.iter_mut().find(|profile| Some(&profile.name) == name.as_ref()) {
+            Some(profile) => &mut profile.profile,
+            None => &mut self.profile,
+        }
+    }
+
+    pub fn bindings(&self) -> &ButtonBindings {
+        self.named_profile()
+            .and_then(|profile| profile.button_bindings.as_ref())
+            .unwrap_or(&self.button_bindings)
+    }
+
+    // Rebinding a profile with its own bindings changes those, not the main ones
+    pub fn bindings_mut(&mut self) -> &mut ButtonBindings {
+        let name = self.active_profile.clone();
+        let profile = self.profiles.iter_mut()
+            .find(|profile| Some(&profile.name) == name.as_ref() && profile.button_bindings.is_some());
+        match profile.and_then(|profile| profile.button_bindings.as_mut()) {
+            Some(bindings) => bindings,
+            None => &mut self.button_bindings,
+        }
+    }
+
+    // Default, then each named profile in turn, then back round
+    pub fn next_profile(&self) -> Option<String> {
+        let next = match self.named_profile() {
+            Some(current) => self.profiles.iter().position(|profile| profile.name == current.name).unwrap() + 1,
+            None => 0,
+        };
+        self.profiles.get(next).map(|profile| profile.name.clone())
+    }
+
+    // Chords that won't parse and OSBs that don't exist, in every profile and button mapping,
+    // so they're found when the config loads rather than when the button is pressed
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = self.profile.problems();
+        for (mfd, position) in ["Left", "Right"].iter().zip(self.cursor_positions) {
+            if position >= 20 {
+                problems.push(format!("{} cursor position: must be below 20", mfd));
+            }
+        }
+        for profile in &self.profiles {
+            problems.extend(profile.profile.problems().into_iter().map(|problem| format!("{}: {}", profile.name, problem)));
+        }
+        let actions = self.midi.iter().map(|mapping| ("MIDI", &mapping.action))
+            .chain(self.direct_buttons.iter().map(|direct| ("Direct button", &direct.action)));
+        for (index, (what, action)) in actions.enumerate() {
+            let what = format!("{} {}", what, index + 1);
+            match action {
+                ButtonAction::Osb(osb) if !(1..=40).contains(osb) => problems.push(format!("{}: OSBs go from 1 to 40", what)),
+                ButtonAction::Macro(chord) => {
+                    if let Err(err) = crate::mfd_keys::parse_chord(chord) {
+                        problems.push(format!("{}: {}", what, err));
+                    }
+                }
+                _ => {}
+            }
         }
+        problems
+    }
+}
+
+// One message for the status line, however many problems there are
+pub fn problems_message(problems: &[String]) -> Option<String> {
+    match problems {
+        [] => None,
+        [problem] => Some(problem.clone()),
+        [problem, rest @ ..] => Some(format!("{} (and {} more)", problem, rest.len())),
+    }
+}
+
+// A config that's already running is only swapped for one without problems
+pub fn check_reload(config: Config) -> Result<Config, String> {
+    match problems_message(&config.problems()) {
+        Some(message) => Err(format!("{} - keeping the current settings", message)),
+        None => Ok(config),
     }
 }
 
 pub static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
 
+const CONFIG_FILE: &str = "superhat.cfg";
+
+// Set by --config; otherwise the file lives in the per-user config directory
+static CONFIG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub fn set_config_path(path: PathBuf) {
+    *CONFIG_PATH.lock().unwrap() = Some(path);
+}
+
+pub fn config_path() -> PathBuf {
+    CONFIG_PATH.lock().unwrap().clone().unwrap_or_else(default_config_path)
+}
+
+fn default_config_path() -> PathBuf {
+    match config_dir() {
+        Some(dir) => dir.join("superhat").join(CONFIG_FILE),
+        None => PathBuf::from(CONFIG_FILE),
+    }
+}
+
+#[cfg(windows)]
+fn config_dir() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(not(windows))]
+fn config_dir() -> Option<PathBuf> {
+    // XDG says relative paths are to be ignored
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+// Write a temporary file next to the config and rename it over the top, so a crash
+// part way through never leaves a half-written config behind
 pub fn save_config(config: &Config) {
+    try_save_config(config).expect("Failed to write config file");
+}
+
+// For one-off commands, which report a failed save rather than panicking
+pub fn try_save_config(config: &Config) -> io::Result<()> {
     let config_str = toml::to_string(config).unwrap();
-    fs::write("superhat.cfg", config_str).expect("Failed to write config file");
+    let path = config_path();
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let temp_path = path.with_extension("cfg.tmp");
+    fs::write(&temp_path, config_str)?;
+    fs::rename(&temp_path, &path)?;
+    *SAVED_AT.lock().unwrap() = modified(&path);
+    Ok(())
+}
+
+// When we last wrote the file ourselves, so our own saves don't look like edits
+static SAVED_AT: Mutex<Option<SystemTime>> = Mutex::new(None);
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Notices when the config is edited while we're running
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
 }
 
-pub fn load_config() -> Config {
-    if Path::new("superhat.cfg").exists() {
-        let config_str = fs::read_to_string("superhat.cfg").expect("Failed to read config file");
-        toml::from_str(&config_str).unwrap_or_default()
-    } else {
-        Config::default()
+impl ConfigWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        ConfigWatcher { modified: modified(&path), path }
+    }
+
+    // The edited config, if the file has changed since the last call
+    pub fn poll(&mut self) -> Option<Result<Config, String>> {
+        let path = &self.path;
+        let modified = modified(path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        if modified.is_none() || modified == *SAVED_AT.lock().unwrap() {
+            return None;
+        }
+        Some(reread_config(path))
+    }
+}
+
+// For a config that's already running. If the file is no good we carry on with the old
+// config, which would be saved over the file sooner or later, so it's backed up too.
+pub fn reread_config(path: &Path) -> Result<Config, String> {
+    fs::read_to_string(path)
+        .map_err(|err| format!("Couldn't read {}: {}", path.display(), err))
+        .and_then(|config_str| parse_config(&config_str))
+        .map_err(|err| back_up(path, err, "keeping the current settings"))
+        .and_then(check_reload)
+}
+
+// A config we couldn't use is copied aside before anything gets the chance to save over
+// it, and the error says where the copy went
+pub fn load_config() -> Result<Config, String> {
+    let path = config_path();
+    if CONFIG_PATH.lock().unwrap().is_none() {
+        migrate_config(Path::new(CONFIG_FILE), &path);
+    }
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let config_str = fs::read_to_string(&path).map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+    parse_config(&config_str).map_err(|err| back_up(&path, err, "using defaults"))
+}
+
+fn back_up(path: &Path, err: String, fallback: &str) -> String {
+    let backup = path.with_extension("cfg.bak");
+    match fs::copy(path, &backup) {
+        Ok(_) => format!("{} - {}, your config is in {}", err, fallback, backup.display()),
+        Err(_) => format!("{} - {}", err, fallback),
+    }
+}
+
+// Each entry takes the file from one version to the next, starting from version 0
+const MIGRATIONS: &[fn(&mut toml::Table)] = &[
+    // Files from before versioning have the same layout as version 1
+    |_| {},
+];
+
+pub fn parse_config(config_str: &str) -> Result<Config, String> {
+    let mut table: toml::Table = config_str.parse().map_err(|err| describe_error(config_str, &err))?;
+    let version = match table.get("version") {
+        None => 0,
+        Some(toml::Value::Integer(version)) => u32::try_from(*version).unwrap_or(u32::MAX),
+        Some(_) => return Err("Config error: version should be a number".to_string()),
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!("Config is version {}, from a newer Superhat than this one (version {})", version, CONFIG_VERSION));
+    }
+    if version == CONFIG_VERSION {
+        // Straight from the text, so errors can say where they are
+        return toml::from_str(config_str).map_err(|err| describe_error(config_str, &err));
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut table);
+    }
+    table.insert("version".to_string(), toml::Value::Integer(CONFIG_VERSION as i64));
+    toml::Value::Table(table).try_into().map_err(|err: toml::de::Error| format!("Config error: {}", err.message()))
+}
+
+// One line with the line and column, rather than toml's multi-line snippet
+fn describe_error(config_str: &str, err: &toml::de::Error) -> String {
+    match err.span() {
+        Some(span) => {
+            let before = &config_str[..span.start];
+            let line = before.matches('\n').count() + 1;
+            let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+            format!("Config error at line {}, column {}: {}", line, column, err.message())
+        }
+        None => format!("Config error: {}", err.message()),
+    }
+}
+
+// Older versions kept the config in the working directory; move it over the first time
+// we run without one in the proper place
+pub fn migrate_config(old_path: &Path, path: &Path) {
+    if path.exists() || !old_path.exists() {
+        return;
+    }
+    if let Some(dir) = path.parent() {
+        if fs::create_dir_all(dir).is_err() {
+            return;
+        }
+    }
+    // Renaming fails across drives, in which case copy and leave the old one be
+    if fs::rename(old_path, path).is_err() {
+        let _ = fs::copy(old_path, path);
     }
 }
 
@@ -54,7 +446,53 @@ pub fn save_mfd_state(mfd: MfdState) {
     if let Ok(mut config_lock) = CONFIG.lock() {
         if let Some(config) = config_lock.as_mut() {
             config.selected_mfd = mfd;
-            save_config(&config);
+            save_config(config);
+        }
+    }
+}
+
+pub fn selection_mode() -> SelectionMode {
+    CONFIG.lock().ok()
+        .and_then(|config| config.as_ref().map(|config| config.selection_mode))
+        .unwrap_or_default()
+}
+
+pub fn repeat_gesture() -> RepeatGesture {
+    CONFIG.lock().ok()
+        .and_then(|config| config.as_ref().map(|config| config.repeat_gesture))
+        .unwrap_or_default()
+}
+
+pub fn shift_layer() -> Option<ShiftLayer> {
+    CONFIG.lock().ok()
+        .and_then(|config| config.as_ref().and_then(|config| config.shift_layer))
+}
+
+pub fn keypad_long_press() -> bool {
+    CONFIG.lock().ok()
+        .and_then(|config| config.as_ref().map(|config| config.keypad_long_press))
+        .unwrap_or_default()
+}
+
+pub fn profile_long_press() -> bool {
+    CONFIG.lock().ok()
+        .and_then(|config| config.as_ref().map(|config| config.profile_long_press))
+        .unwrap_or_default()
+}
+
+pub fn cursor_position(mfd: &MfdState) -> u8 {
+    CONFIG.lock().ok()
+        .and_then(|config| config.as_ref().map(|config| config.cursor_positions[mfd.index()] % 20))
+        .unwrap_or_default()
+}
+
+pub fn save_cursor_position(mfd: &MfdState, position: u8) {
+    if let Ok(mut config_lock) = CONFIG.lock() {
+        if let Some(config) = config_lock.as_mut() {
+            if config.cursor_positions[mfd.index()] != position {
+                config.cursor_positions[mfd.index()] = position;
+                save_config(config);
+            }
         }
     }
-} 
\ No newline at end of file
+}
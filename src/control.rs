@@ -0,0 +1,149 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::cli;
+
+// Set from the signal handlers and picked up by the main loop
+static STOP: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+// Ctrl+C and SIGTERM stop Superhat cleanly, letting go of any keys; SIGHUP reloads the config.
+// Needs to be called from inside the runtime.
+pub fn watch_signals() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            STOP.store(true, Ordering::Relaxed);
+        }
+    });
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        for (kind, flag) in [(SignalKind::terminate(), &STOP), (SignalKind::hangup(), &RELOAD)] {
+            let Ok(mut stream) = signal(kind) else { continue };
+            tokio::spawn(async move {
+                while stream.recv().await.is_some() {
+                    flag.store(true, Ordering::Relaxed);
+                }
+            });
+        }
+    }
+}
+
+pub fn stop_requested() -> bool {
+    STOP.load(Ordering::Relaxed)
+}
+
+pub fn take_reload_request() -> bool {
+    RELOAD.swap(false, Ordering::Relaxed)
+}
+
+// What can be asked over the control socket, one line at a time
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    Press(u8),                // OSB 1-40, tapped
+    Profile(Option<String>),  // Switch to the named profile, or just say which one is active
+    Reload,
+    Status,
+    Quit,
+    Show,  // Another launch wants this one in front
+}
+
+pub fn parse_request(line: &str) -> Result<Request, String> {
+    let line = line.trim();
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim();
+    match command {
+        "press" => Ok(Request::Press(cli::parse_press(&mut rest.split_whitespace())?)),
+        // Profile names can have spaces in them
+        "profile" => Ok(Request::Profile(Some(rest.to_string()).filter(|name| !name.is_empty()))),
+        "reload" if rest.is_empty() => Ok(Request::Reload),
+        "status" if rest.is_empty() => Ok(Request::Status),
+        "quit" if rest.is_empty() => Ok(Request::Quit),
+        "show" if rest.is_empty() => Ok(Request::Show),
+        _ => Err(format!("Unknown request '{}'; try press, profile, reload, status, show or quit", line)),
+    }
+}
+
+// A connection sending requests, with whatever part of a line it has sent so far
+pub struct Client<S> {
+    stream: S,
+    buffer: Vec<u8>,
+}
+
+impl<S: Read + Write> Client<S> {
+    pub fn new(stream: S) -> Self {
+        Client { stream, buffer: Vec::new() }
+    }
+}
+
+// Answers every whole line the clients have sent with what `handle` returns, and drops the
+// clients that have gone. The streams need to be non-blocking. Says whether there were any.
+pub fn serve<S: Read + Write>(clients: &mut Vec<Client<S>>, handle: &mut impl FnMut(Result<Request, String>) -> String) -> bool {
+    let mut handled = false;
+    clients.retain_mut(|client| {
+        let mut open = true;
+        let mut bytes = [0; 512];
+        loop {
+            match client.stream.read(&mut bytes) {
+                Ok(0) => {
+                    open = false;
+                    break;
+                }
+                Ok(len) => client.buffer.extend_from_slice(&bytes[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => return false,
+            }
+        }
+        while let Some(end) = client.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = client.buffer.drain(..=end).collect();
+            let reply = handle(parse_request(&String::from_utf8_lossy(&line)));
+            handled = true;
+            // Replies are short, so a client that can't take one isn't worth waiting for
+            if writeln!(client.stream, "{}", reply).is_err() {
+                return false;
+            }
+        }
+        open
+    });
+    handled
+}
+
+// Sends one request and waits for the answer
+pub fn send<S: Read + Write>(mut stream: S, line: &str) -> io::Result<String> {
+    writeln!(stream, "{}", line)?;
+    let mut reply = Vec::new();
+    let mut byte = [0; 1];
+    while stream.read(&mut byte)? == 1 && byte[0] != b'\n' {
+        reply.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+// Listens on localhost only; anything that can reach it can press OSBs
+pub struct ControlServer {
+    listener: TcpListener,
+    clients: Vec<Client<TcpStream>>,
+}
+
+impl ControlServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(ControlServer { listener, clients: Vec::new() })
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.listener.local_addr().ok().map(|address| address.port())
+    }
+
+    // Takes whatever has come in since the last call. Says whether there was anything.
+    pub fn poll(&mut self, mut handle: impl FnMut(Result<Request, String>) -> String) -> bool {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client::new(stream));
+            }
+        }
+        serve(&mut self.clients, &mut handle)
+    }
+}
@@ -0,0 +1,116 @@
+use std::io;
+use std::net::UdpSocket;
+
+use crate::config::NamedProfile;
+
+// Picks a profile from what's running. Only a change in what's detected switches
+// profile, so picking one by hand sticks until the sim or aircraft changes.
+#[derive(Default)]
+pub struct ProfileDetector {
+    telemetry: Option<UdpSocket>,
+    port: Option<u16>,
+    aircraft: Option<String>,  // Last aircraft the feed reported
+    detected: Option<String>,  // Profile picked last time round
+}
+
+impl ProfileDetector {
+    // The feed is one UDP datagram per update on localhost, holding just the aircraft name.
+    // The port is remembered even if we can't have it, so we don't keep on trying.
+    pub fn listen(&mut self, port: Option<u16>) -> io::Result<()> {
+        self.port = port;
+        self.telemetry = None;
+        self.aircraft = None;
+        let Some(port) = port else { return Ok(()) };
+        let socket = UdpSocket::bind(("127.0.0.1", port))?;
+        socket.set_nonblocking(true)?;
+        self.telemetry = Some(socket);
+        Ok(())
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    // The profile to switch to, if what's running has changed since the last call
+    pub fn poll(&mut self, profiles: &[NamedProfile]) -> Option<String> {
+        self.read_telemetry();
+        let watching_processes = profiles.iter().any(|profile| !profile.processes.is_empty());
+        let processes = if watching_processes { running_processes() } else { Vec::new() };
+        let detected = detect(profiles, &processes, self.aircraft.as_deref());
+        if detected == self.detected {
+            return None;
+        }
+        self.detected = detected.clone();
+        detected
+    }
+
+    fn read_telemetry(&mut self) {
+        let Some(socket) = &self.telemetry else { return };
+        let mut buf = [0; 256];
+        // Only the latest report matters
+        while let Ok(len) = socket.recv(&mut buf) {
+            let aircraft = String::from_utf8_lossy(&buf[..len]).trim().to_string();
+            self.aircraft = Some(aircraft).filter(|aircraft| !aircraft.is_empty());
+        }
+    }
+}
+
+// An aircraft match wins over a process match, being the more specific of the two
+pub fn detect(profiles: &[NamedProfile], processes: &[String], aircraft: Option<&str>) -> Option<String> {
+    let by_aircraft = aircraft.and_then(|aircraft| {
+        profiles.iter().find(|profile| profile.aircraft.iter().any(|name| name.eq_ignore_ascii_case(aircraft)))
+    });
+    let by_process = || profiles.iter().find(|profile| {
+        profile.processes.iter().any(|name| processes.iter().any(|process| process.eq_ignore_ascii_case(name)))
+    });
+    by_aircraft.or_else(by_process).map(|profile| profile.name.clone())
+}
+
+// Wine shows Windows paths, so either kind of separator can come before the name
+#[cfg(target_os = "linux")]
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+// Executable names, from the command line since comm is cut short at 15 characters
+#[cfg(target_os = "linux")]
+pub fn running_processes() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/proc") else { return Vec::new() };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().bytes().all(|b| b.is_ascii_digit()))
+        .filter_map(|entry| std::fs::read(entry.path().join("cmdline")).ok())
+        .filter_map(|cmdline| {
+            let program = cmdline.split(|b| *b == 0).next()?;
+            let program = String::from_utf8_lossy(program);
+            Some(file_name(&program).to_string()).filter(|name| !name.is_empty())
+        })
+        .collect()
+}
+
+#[cfg(windows)]
+pub fn running_processes() -> Vec<String> {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+    };
+
+    let mut processes = Vec::new();
+    unsafe {
+        let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) else { return processes };
+        let mut entry = PROCESSENTRY32W { dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32, ..Default::default() };
+        let mut more = Process32FirstW(snapshot, &mut entry).as_bool();
+        while more {
+            let len = entry.szExeFile.iter().position(|c| *c == 0).unwrap_or(entry.szExeFile.len());
+            processes.push(String::from_utf16_lossy(&entry.szExeFile[..len]));
+            more = Process32NextW(snapshot, &mut entry).as_bool();
+        }
+        CloseHandle(snapshot);
+    }
+    processes
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn running_processes() -> Vec<String> {
+    Vec::new()
+}
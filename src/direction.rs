@@ -1,4 +1,6 @@
-#[derive(Debug, Copy, Clone, PartialEq)]
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Direction {
     Up,
     Right,
@@ -13,4 +15,13 @@ pub fn get_relative_directions(side: Direction) -> (Direction, Direction) {
         Direction::Down => (Direction::Right, Direction::Left),
         Direction::Left => (Direction::Down, Direction::Up),
     }
-} 
\ No newline at end of file
+}
+
+pub fn get_opposite_direction(direction: Direction) -> Direction {
+    match direction {
+        Direction::Up => Direction::Down,
+        Direction::Right => Direction::Left,
+        Direction::Down => Direction::Up,
+        Direction::Left => Direction::Right,
+    }
+}
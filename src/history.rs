@@ -0,0 +1,24 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use crate::MfdState;
+
+pub const HISTORY_LENGTH: usize = 5;
+
+// Recently pressed OSBs for each MFD, most recent first, without repeats
+static HISTORY: Mutex<[VecDeque<u8>; 2]> = Mutex::new([VecDeque::new(), VecDeque::new()]);
+
+pub fn record(mfd: &MfdState, osb_number: u8) {
+    let mut history = HISTORY.lock().unwrap();
+    let entries = &mut history[mfd.index()];
+    entries.retain(|osb| *osb != osb_number);
+    entries.push_front(osb_number);
+    entries.truncate(HISTORY_LENGTH);
+}
+
+pub fn recent(mfd: &MfdState) -> Vec<u8> {
+    HISTORY.lock().unwrap()[mfd.index()].iter().copied().collect()
+}
+
+pub fn last(mfd: &MfdState) -> Option<u8> {
+    HISTORY.lock().unwrap()[mfd.index()].front().copied()
+}
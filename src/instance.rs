@@ -0,0 +1,60 @@
+use std::io;
+
+use crate::cli::Command;
+
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+pub use unix::Guard;
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub use windows::Guard;
+
+pub const INSTANCE_NAME: &str = "Superhat";
+
+// Only one Superhat sends keys at a time. Starting another one hands its command to the
+// one that's already running instead.
+pub enum Claim {
+    First(Guard),
+    Running,
+}
+
+pub fn claim() -> io::Result<Claim> {
+    Guard::claim(INSTANCE_NAME)
+}
+
+// What a second launch asks the running instance to do, as a control request line
+pub fn forwarded_request(command: &Command) -> Option<String> {
+    match command {
+        Command::Run | Command::Bind => Some("show".to_string()),
+        Command::Press(osb) => {
+            let side = if *osb > 20 { "right" } else { "left" };
+            Some(format!("press {} {}", side, (osb - 1) % 20 + 1))
+        }
+        _ => None,
+    }
+}
+
+// The running instance's answer
+pub fn forward(line: &str) -> io::Result<String> {
+    Guard::forward(INSTANCE_NAME, line)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub struct Guard;
+
+#[cfg(not(any(unix, windows)))]
+impl Guard {
+    fn claim(_name: &str) -> io::Result<Claim> {
+        Ok(Claim::First(Guard))
+    }
+
+    pub fn poll(&mut self, _handle: impl FnMut(Result<crate::control::Request, String>) -> String) -> bool {
+        false
+    }
+
+    fn forward(_name: &str, _line: &str) -> io::Result<String> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Superhat is already running"))
+    }
+}
@@ -0,0 +1,80 @@
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::Claim;
+use crate::control::{self, Client, Request};
+
+// A lock file says who's running, and a socket next to it takes requests from later launches.
+// The lock goes with the process, so a crash never leaves a stale one behind.
+pub struct Guard {
+    _lock: File,
+    socket_path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<Client<UnixStream>>,
+}
+
+// Per-user where there's a runtime dir, so two users each get their own
+fn runtime_dir() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(env::temp_dir)
+}
+
+fn paths(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+    let name = name.to_lowercase();
+    (dir.join(format!("{}.lock", name)), dir.join(format!("{}.sock", name)))
+}
+
+impl Guard {
+    pub(super) fn claim(name: &str) -> io::Result<Claim> {
+        Self::claim_in(&runtime_dir(), name)
+    }
+
+    pub fn claim_in(dir: &Path, name: &str) -> io::Result<Claim> {
+        let (lock_path, socket_path) = paths(dir, name);
+        let lock = File::options().create(true).truncate(false).write(true).open(&lock_path)?;
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::WouldBlock => Ok(Claim::Running),
+                _ => Err(err),
+            };
+        }
+
+        // Whoever had the lock before us is gone, so their socket is too
+        let _ = fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Claim::First(Guard { _lock: lock, socket_path, listener, clients: Vec::new() }))
+    }
+
+    // Requests from later launches, answered like the control socket's. Says whether there were any.
+    pub fn poll(&mut self, mut handle: impl FnMut(Result<Request, String>) -> String) -> bool {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client::new(stream));
+            }
+        }
+        control::serve(&mut self.clients, &mut handle)
+    }
+
+    pub(super) fn forward(name: &str, line: &str) -> io::Result<String> {
+        Self::forward_in(&runtime_dir(), name, line)
+    }
+
+    pub fn forward_in(dir: &Path, name: &str, line: &str) -> io::Result<String> {
+        let (_, socket_path) = paths(dir, name);
+        let stream = UnixStream::connect(socket_path)?;
+        stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+        control::send(stream, line)
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket_path);
+    }
+}
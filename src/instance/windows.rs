@@ -0,0 +1,152 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use windows::Win32::Foundation::{HANDLE, HWND, BOOL, CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, ERROR_BROKEN_PIPE, ERROR_NO_DATA, ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE};
+use windows::Win32::Storage::FileSystem::{ReadFile, WriteFile, PIPE_ACCESS_DUPLEX};
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_NOWAIT, PIPE_READMODE_BYTE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES,
+};
+use windows::Win32::System::Threading::CreateMutexW;
+use windows::Win32::UI::WindowsAndMessaging::{FindWindowA, SetForegroundWindow, ShowWindow, SW_RESTORE};
+use windows::core::{PCSTR, PCWSTR};
+
+use super::Claim;
+use crate::control::{self, Client, Request};
+
+// A named mutex says who's running, and a named pipe takes requests from later launches.
+// Windows lets go of both when the process ends, however it ends.
+pub struct Guard {
+    mutex_handle: HANDLE,
+    pipe_name: Vec<u16>,
+    listening: Option<Pipe>,  // The instance waiting for the next launch to connect
+    clients: Vec<Client<Pipe>>,
+}
+
+fn pipe_path(name: &str) -> String {
+    format!(r"\\.\pipe\{}", name)
+}
+
+// Our end of one connection. It never blocks, so it can be served like the control socket.
+struct Pipe(HANDLE);
+
+impl Pipe {
+    fn listen(name: &[u16]) -> io::Result<Pipe> {
+        let handle = unsafe {
+            CreateNamedPipeW(
+                PCWSTR::from_raw(name.as_ptr()),
+                PIPE_ACCESS_DUPLEX,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_NOWAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                512,
+                512,
+                0,
+                None,
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Pipe(handle))
+    }
+
+    // Without blocking, connecting fails with ERROR_PIPE_CONNECTED once a client is there, or
+    // ERROR_NO_DATA if it has already written and gone
+    fn connected(&self) -> bool {
+        unsafe { !ConnectNamedPipe(self.0, None).as_bool() && matches!(GetLastError(), ERROR_PIPE_CONNECTED | ERROR_NO_DATA) }
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        if unsafe { ReadFile(self.0, Some(buf.as_mut_ptr().cast()), buf.len() as u32, Some(&mut read), None) }.as_bool() {
+            return Ok(read as usize);
+        }
+        match unsafe { GetLastError() } {
+            ERROR_NO_DATA => Err(io::ErrorKind::WouldBlock.into()),
+            ERROR_BROKEN_PIPE => Ok(0),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        if unsafe { WriteFile(self.0, Some(buf), Some(&mut written), None) }.as_bool() {
+            Ok(written as usize)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            DisconnectNamedPipe(self.0);
+            let _ = CloseHandle(self.0);
+        }
+    }
+}
+
+impl Guard {
+    pub(super) fn claim(name: &str) -> io::Result<Claim> {
+        let wide_name: Vec<u16> = format!("Global\\{}_mutex", name).encode_utf16().chain(Some(0)).collect();
+        let mutex_handle = unsafe {
+            let handle = CreateMutexW(None, BOOL::from(false), PCWSTR::from_raw(wide_name.as_ptr()))
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            if GetLastError() == ERROR_ALREADY_EXISTS {
+                let _ = CloseHandle(handle);
+                return Ok(Claim::Running);
+            }
+            handle
+        };
+        let pipe_name: Vec<u16> = pipe_path(name).encode_utf16().chain(Some(0)).collect();
+        let listening = Some(Pipe::listen(&pipe_name)?);
+        Ok(Claim::First(Guard { mutex_handle, pipe_name, listening, clients: Vec::new() }))
+    }
+
+    // Requests from later launches, answered like the control socket's. Says whether there were any.
+    pub fn poll(&mut self, mut handle: impl FnMut(Result<Request, String>) -> String) -> bool {
+        while let Some(pipe) = self.listening.take_if(|pipe| pipe.connected()) {
+            self.clients.push(Client::new(pipe));
+            self.listening = Pipe::listen(&self.pipe_name).ok();
+        }
+        if self.listening.is_none() {
+            self.listening = Pipe::listen(&self.pipe_name).ok();
+        }
+        control::serve(&mut self.clients, &mut handle)
+    }
+
+    // Bringing the window up has to come from the launch the user is looking at
+    pub(super) fn forward(name: &str, line: &str) -> io::Result<String> {
+        if line == "show" {
+            let window_name = CString::new(name).unwrap();
+            unsafe {
+                let existing_window = FindWindowA(PCSTR::null(), PCSTR::from_raw(window_name.as_ptr() as *const u8));
+                if existing_window != HWND(0) {
+                    ShowWindow(existing_window, SW_RESTORE);
+                    SetForegroundWindow(existing_window);
+                }
+            }
+            return Ok(format!("{} is already running", name));
+        }
+
+        let pipe = File::options().read(true).write(true).open(pipe_path(name))?;
+        control::send(pipe, line)
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.mutex_handle);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::io;
+
+#[cfg(target_os = "linux")]
+mod uinput;
+#[cfg(windows)]
+mod vjoy;
+
+// One button per OSB across both MFDs
+pub const BUTTON_COUNT: u8 = 40;
+
+// A virtual game controller that the sim sees as an ordinary joystick.
+// Buttons are numbered from 1 so OSB numbers can be used directly.
+pub trait VirtualJoystick: Send {
+    fn set_button(&mut self, button: u8, pressed: bool) -> io::Result<()>;
+}
+
+#[cfg(target_os = "linux")]
+pub fn open() -> io::Result<Box<dyn VirtualJoystick>> {
+    Ok(Box::new(uinput::UinputJoystick::new("Superhat", BUTTON_COUNT)?))
+}
+
+#[cfg(windows)]
+pub fn open() -> io::Result<Box<dyn VirtualJoystick>> {
+    // vJoy devices are configured by the user with vJoyConf; we always use device 1
+    Ok(Box::new(vjoy::VJoyDevice::acquire(1)?))
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn open() -> io::Result<Box<dyn VirtualJoystick>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Virtual joystick output is not supported on this platform"))
+}
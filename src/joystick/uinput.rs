@@ -0,0 +1,112 @@
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+use super::VirtualJoystick;
+
+// From linux/input-event-codes.h and linux/uinput.h
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const SYN_REPORT: u16 = 0;
+const BUS_VIRTUAL: u16 = 0x06;
+
+const BTN_JOYSTICK: u16 = 0x120;       // BTN_TRIGGER..BTN_DEAD, 16 buttons
+const BTN_TRIGGER_HAPPY: u16 = 0x2c0;  // BTN_TRIGGER_HAPPY1..40
+
+const UI_DEV_CREATE: libc::c_ulong = 0x5501;
+const UI_DEV_DESTROY: libc::c_ulong = 0x5502;
+const UI_DEV_SETUP: libc::c_ulong = 0x405c5503;
+const UI_SET_EVBIT: libc::c_ulong = 0x40045564;
+const UI_SET_KEYBIT: libc::c_ulong = 0x40045565;
+
+// Button 1-16 use the classic joystick codes so udev tags the device as a joystick,
+// the rest spill over into the trigger-happy range. Both ranges are contiguous so
+// the sim sees buttons in the same order as the OSBs.
+pub fn button_code(button: u8) -> Option<u16> {
+    match button {
+        1..=16 => Some(BTN_JOYSTICK + button as u16 - 1),
+        17..=56 => Some(BTN_TRIGGER_HAPPY + button as u16 - 17),
+        _ => None,
+    }
+}
+
+pub struct UinputJoystick {
+    file: File,
+}
+
+impl UinputJoystick {
+    pub fn new(name: &str, button_count: u8) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/uinput")?;
+        let fd = file.as_raw_fd();
+
+        unsafe {
+            check(libc::ioctl(fd, UI_SET_EVBIT, EV_KEY as libc::c_int))?;
+            for button in 1..=button_count {
+                let code = button_code(button)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Too many joystick buttons"))?;
+                check(libc::ioctl(fd, UI_SET_KEYBIT, code as libc::c_int))?;
+            }
+
+            let mut setup: libc::uinput_setup = mem::zeroed();
+            setup.id.bustype = BUS_VIRTUAL;
+            setup.id.vendor = 0x1209;   // pid.codes open source vendor id
+            setup.id.product = 0x5348;
+            setup.id.version = 1;
+            let name = CString::new(name).unwrap();
+            for (dst, src) in setup.name.iter_mut().zip(name.as_bytes().iter().take(libc::UINPUT_MAX_NAME_SIZE - 1)) {
+                *dst = *src as libc::c_char;
+            }
+
+            check(libc::ioctl(fd, UI_DEV_SETUP, &setup))?;
+            check(libc::ioctl(fd, UI_DEV_CREATE))?;
+        }
+
+        Ok(UinputJoystick { file })
+    }
+
+    fn emit(&mut self, type_: u16, code: u16, value: i32) -> io::Result<()> {
+        let mut event: libc::input_event = unsafe { mem::zeroed() };
+        event.type_ = type_;
+        event.code = code;
+        event.value = value;
+
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                &event as *const libc::input_event as *const u8,
+                mem::size_of::<libc::input_event>(),
+            )
+        };
+        self.file.write_all(bytes)
+    }
+}
+
+impl VirtualJoystick for UinputJoystick {
+    fn set_button(&mut self, button: u8, pressed: bool) -> io::Result<()> {
+        let code = button_code(button)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid joystick button"))?;
+        self.emit(EV_KEY, code, pressed as i32)?;
+        self.emit(EV_SYN, SYN_REPORT, 0)
+    }
+}
+
+impl Drop for UinputJoystick {
+    fn drop(&mut self) {
+        unsafe {
+            libc::ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY);
+        }
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::io;
+use windows::core::s;
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryA};
+
+use super::{VirtualJoystick, BUTTON_COUNT};
+
+// vJoyInterface.dll exports, see the vJoy SDK's public.h
+type AcquireVjdFn = unsafe extern "C" fn(rid: u32) -> i32;
+type RelinquishVjdFn = unsafe extern "C" fn(rid: u32);
+type GetVjdButtonNumberFn = unsafe extern "C" fn(rid: u32) -> i32;
+type SetBtnFn = unsafe extern "C" fn(value: i32, rid: u32, button: u8) -> i32;
+
+pub struct VJoyDevice {
+    id: u32,
+    relinquish: RelinquishVjdFn,
+    set_btn: SetBtnFn,
+}
+
+impl VJoyDevice {
+    pub fn acquire(id: u32) -> io::Result<Self> {
+        unsafe {
+            // The DLL is installed alongside the vJoy driver and left loaded for the life of the process
+            let module = LoadLibraryA(s!("vJoyInterface.dll")).map_err(|_| {
+                io::Error::new(io::ErrorKind::NotFound, "vJoyInterface.dll not found - is vJoy installed?")
+            })?;
+
+            let missing = || io::Error::new(io::ErrorKind::NotFound, "vJoyInterface.dll is missing exports");
+            let acquire: AcquireVjdFn = std::mem::transmute(GetProcAddress(module, s!("AcquireVJD")).ok_or_else(missing)?);
+            let button_number: GetVjdButtonNumberFn = std::mem::transmute(GetProcAddress(module, s!("GetVJDButtonNumber")).ok_or_else(missing)?);
+            let relinquish: RelinquishVjdFn = std::mem::transmute(GetProcAddress(module, s!("RelinquishVJD")).ok_or_else(missing)?);
+            let set_btn: SetBtnFn = std::mem::transmute(GetProcAddress(module, s!("SetBtn")).ok_or_else(missing)?);
+
+            if acquire(id) == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Could not acquire vJoy device {} - is it enabled and free?", id),
+                ));
+            }
+
+            if button_number(id) < BUTTON_COUNT as i32 {
+                relinquish(id);
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("vJoy device {} needs at least {} buttons - change it in vJoyConf", id, BUTTON_COUNT),
+                ));
+            }
+
+            Ok(VJoyDevice { id, relinquish, set_btn })
+        }
+    }
+}
+
+impl VirtualJoystick for VJoyDevice {
+    fn set_button(&mut self, button: u8, pressed: bool) -> io::Result<()> {
+        // vJoy buttons are 1-based, same as ours
+        if unsafe { (self.set_btn)(pressed as i32, self.id, button) } == 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("vJoy rejected button {}", button)));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for VJoyDevice {
+    fn drop(&mut self) {
+        unsafe { (self.relinquish)(self.id) };
+    }
+}
@@ -0,0 +1,46 @@
+use crossterm::event::KeyCode;
+
+// Keys come through the same pipeline as joystick buttons, as buttons on a device of their own
+pub const KEYBOARD_DEVICE: u32 = u32::MAX;
+
+// Past the end of Unicode, so they can't clash with characters
+const SPECIAL_KEYS: u32 = 0x110000;
+const FUNCTION_KEYS: u32 = SPECIAL_KEYS + 0x100;
+
+// In id order after SPECIAL_KEYS
+const SPECIAL_KEY_CODES: [(KeyCode, &str); 13] = [
+    (KeyCode::Up, "Up"),
+    (KeyCode::Down, "Down"),
+    (KeyCode::Left, "Left"),
+    (KeyCode::Right, "Right"),
+    (KeyCode::Enter, "Enter"),
+    (KeyCode::Tab, "Tab"),
+    (KeyCode::Backspace, "Backspace"),
+    (KeyCode::Home, "Home"),
+    (KeyCode::End, "End"),
+    (KeyCode::PageUp, "PageUp"),
+    (KeyCode::PageDown, "PageDown"),
+    (KeyCode::Insert, "Insert"),
+    (KeyCode::Delete, "Delete"),
+];
+
+// The button id for a key, or None for keys that can't be bound
+pub fn key_id(code: KeyCode) -> Option<u32> {
+    let id = match code {
+        KeyCode::Char(c) => c.to_ascii_lowercase() as u32,
+        KeyCode::F(n) => FUNCTION_KEYS + n as u32,
+        _ => SPECIAL_KEYS + SPECIAL_KEY_CODES.iter().position(|(special, _)| *special == code)? as u32,
+    };
+    Some(id)
+}
+
+// For showing a bound key back to the user
+pub fn key_name(id: u32) -> String {
+    match id {
+        FUNCTION_KEYS.. => format!("F{}", id - FUNCTION_KEYS),
+        SPECIAL_KEYS.. => SPECIAL_KEY_CODES.get((id - SPECIAL_KEYS) as usize)
+            .map_or_else(|| format!("#{}", id), |(_, name)| name.to_string()),
+        0x20 => "Space".to_string(),
+        _ => char::from_u32(id).map_or_else(|| format!("#{}", id), |c| c.to_ascii_uppercase().to_string()),
+    }
+}
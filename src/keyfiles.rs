@@ -0,0 +1,116 @@
+use crate::mfd_keys::MFD_KEYS;
+
+// DirectInput scan codes as BMS writes them, for the keys chords can name
+static SCAN_CODES: &[(u32, &str)] = &[
+    (0x02, "1"), (0x03, "2"), (0x04, "3"), (0x05, "4"), (0x06, "5"),
+    (0x07, "6"), (0x08, "7"), (0x09, "8"), (0x0A, "9"), (0x0B, "0"),
+    (0x10, "Q"), (0x11, "W"), (0x12, "E"), (0x13, "R"), (0x14, "T"), (0x15, "Y"), (0x16, "U"), (0x17, "I"),
+    (0x18, "O"), (0x19, "P"), (0x1E, "A"), (0x1F, "S"), (0x20, "D"), (0x21, "F"), (0x22, "G"), (0x23, "H"),
+    (0x24, "J"), (0x25, "K"), (0x26, "L"), (0x2C, "Z"), (0x2D, "X"), (0x2E, "C"), (0x2F, "V"), (0x30, "B"),
+    (0x31, "N"), (0x32, "M"),
+    (0x3B, "F1"), (0x3C, "F2"), (0x3D, "F3"), (0x3E, "F4"), (0x3F, "F5"), (0x40, "F6"),
+    (0x41, "F7"), (0x42, "F8"), (0x43, "F9"), (0x44, "F10"), (0x57, "F11"), (0x58, "F12"),
+    (0x47, "Numpad7"), (0x48, "Numpad8"), (0x49, "Numpad9"), (0x4B, "Numpad4"), (0x4C, "Numpad5"),
+    (0x4D, "Numpad6"), (0x4F, "Numpad1"), (0x50, "Numpad2"), (0x51, "Numpad3"), (0x52, "Numpad0"),
+];
+
+// Modifier bits in a BMS key file, in the order chords list them
+static MODIFIERS: &[(u32, &str)] = &[(2, "Ctrl"), (1, "Shift"), (4, "Alt")];
+
+// The OSB chords from a BMS .key file, plus a note for each OSB line we couldn't use.
+// Lines look like: SimCBEOSB_1L 126 0 0x2 6 0 0 1 "LMFD: OSB-1 Button - Push"
+pub fn parse_bms_keys(text: &str) -> (Vec<(u8, String)>, Vec<String>) {
+    let mut chords = Vec::new();
+    let mut skipped = Vec::new();
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let Some(osb_number) = fields.first().and_then(|callback| bms_osb(callback)) else { continue };
+        let number = |index: usize| fields.get(index).and_then(|field| parse_number(field));
+        let (Some(scan_code), Some(modifiers), Some(combo)) = (number(3), number(4), number(5)) else {
+            skipped.push(format!("{}: not a key line", fields[0]));
+            continue;
+        };
+        if scan_code == 0xFFFFFFFF {
+            continue;  // Not bound to a key
+        }
+        if combo != 0 {
+            skipped.push(format!("{}: two-key combos can't be sent as one chord", fields[0]));
+            continue;
+        }
+        let Some((_, key)) = SCAN_CODES.iter().find(|(code, _)| *code == scan_code) else {
+            skipped.push(format!("{}: unknown key 0x{:X}", fields[0], scan_code));
+            continue;
+        };
+        let mut chord: Vec<&str> = MODIFIERS.iter().filter(|(bit, _)| modifiers & bit != 0).map(|(_, name)| *name).collect();
+        chord.push(key);
+        chords.push((osb_number, chord.join("+")));
+    }
+    (chords, skipped)
+}
+
+// SimCBEOSB_<1-20><L|R>, numbered 1-40 the way we do
+fn bms_osb(callback: &str) -> Option<u8> {
+    let osb = callback.strip_prefix("SimCBEOSB_")?;
+    let (number, side) = osb.split_at(osb.len().checked_sub(1)?);
+    let number: u8 = number.parse().ok().filter(|number| (1..=20).contains(number))?;
+    match side {
+        "L" => Some(number),
+        "R" => Some(number + 20),
+        _ => None,
+    }
+}
+
+fn parse_number(field: &str) -> Option<u32> {
+    match field.strip_prefix("0x").or_else(|| field.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => field.parse::<i64>().ok().map(|number| number as u32),
+    }
+}
+
+// The F-16C's MFDs, as DCS numbers them
+const DCS_LEFT_MFD: u32 = 24;
+const DCS_RIGHT_MFD: u32 = 25;
+const DCS_FIRST_OSB: u32 = 3001;
+
+// A keyboard diff.lua for the DCS F-16C binding each OSB to the key Superhat sends for it.
+// `chord` gives the chord for each OSB, 1-40.
+pub fn dcs_keyboard_diff(chord: impl Fn(u8) -> Option<String>) -> String {
+    let mut lua = String::from("local diff = {\n\t[\"keyDiffs\"] = {\n");
+    for osb_number in 1..=40u8 {
+        let (device, side, number) = match osb_number {
+            1..=20 => (DCS_LEFT_MFD, "Left", osb_number),
+            _ => (DCS_RIGHT_MFD, "Right", osb_number - 20),
+        };
+        let chord = chord(osb_number).unwrap_or_else(|| MFD_KEYS[osb_number as usize - 1].to_string());
+        let mut names: Vec<String> = chord.split('+').map(|name| dcs_key_name(name.trim())).collect();
+        let key = names.pop().unwrap_or_default();
+        let command = DCS_FIRST_OSB + number as u32 - 1;
+
+        lua += &format!("\t\t[\"d{0}pnilu{0}cd{1}vd1vpnilvu0\"] = {{\n", command, device);
+        lua += "\t\t\t[\"added\"] = {\n\t\t\t\t[1] = {\n";
+        lua += &format!("\t\t\t\t\t[\"key\"] = \"{}\",\n", key);
+        if !names.is_empty() {
+            lua += "\t\t\t\t\t[\"reformers\"] = {\n";
+            for (index, name) in names.iter().enumerate() {
+                lua += &format!("\t\t\t\t\t\t[{}] = \"{}\",\n", index + 1, name);
+            }
+            lua += "\t\t\t\t\t},\n";
+        }
+        lua += "\t\t\t\t},\n\t\t\t},\n";
+        lua += &format!("\t\t\t[\"name\"] = \"{} MFD OSB {}\",\n\t\t}},\n", side, number);
+    }
+    lua += "\t},\n}\nreturn diff\n";
+    lua
+}
+
+fn dcs_key_name(name: &str) -> String {
+    match name.to_ascii_lowercase().as_str() {
+        "ctrl" | "control" => "LCtrl".to_string(),
+        "alt" => "LAlt".to_string(),
+        "shift" => "LShift".to_string(),
+        lower => match lower.strip_prefix("numpad") {
+            Some(digit) => format!("Num{}", digit),
+            None => name.to_ascii_uppercase(),
+        },
+    }
+}
@@ -0,0 +1,59 @@
+use serde::{Serialize, Deserialize};
+use crate::direction::Direction;
+
+// The F-16 ICP keys, laid out as a 4x4 grid with the DCS rocker along the bottom
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum KeypadKey {
+    One, Two, Three, Rcl,
+    Four, Five, Six, Entr,
+    Seven, Eight, Nine, Zero,
+    Rtn, Up, Down, Seq,
+}
+
+pub const KEYPAD_COLUMNS: u8 = 4;
+
+pub const KEYPAD_KEYS: [KeypadKey; 16] = [
+    KeypadKey::One, KeypadKey::Two, KeypadKey::Three, KeypadKey::Rcl,
+    KeypadKey::Four, KeypadKey::Five, KeypadKey::Six, KeypadKey::Entr,
+    KeypadKey::Seven, KeypadKey::Eight, KeypadKey::Nine, KeypadKey::Zero,
+    KeypadKey::Rtn, KeypadKey::Up, KeypadKey::Down, KeypadKey::Seq,
+];
+
+// Where the cursor starts when the keypad is opened
+pub const KEYPAD_HOME: u8 = 5;
+
+impl KeypadKey {
+    pub fn label(&self) -> &'static str {
+        match self {
+            KeypadKey::One => "1",
+            KeypadKey::Two => "2",
+            KeypadKey::Three => "3",
+            KeypadKey::Rcl => "RCL",
+            KeypadKey::Four => "4",
+            KeypadKey::Five => "5",
+            KeypadKey::Six => "6",
+            KeypadKey::Entr => "ENTR",
+            KeypadKey::Seven => "7",
+            KeypadKey::Eight => "8",
+            KeypadKey::Nine => "9",
+            KeypadKey::Zero => "0",
+            KeypadKey::Rtn => "RTN",
+            KeypadKey::Up => "UP",
+            KeypadKey::Down => "DN",
+            KeypadKey::Seq => "SEQ",
+        }
+    }
+}
+
+// Move around the grid, wrapping within the row or column
+pub fn move_keypad_cursor(position: u8, direction: Direction) -> u8 {
+    let rows = KEYPAD_KEYS.len() as u8 / KEYPAD_COLUMNS;
+    let (row, column) = (position / KEYPAD_COLUMNS, position % KEYPAD_COLUMNS);
+    let (row, column) = match direction {
+        Direction::Up => ((row + rows - 1) % rows, column),
+        Direction::Down => ((row + 1) % rows, column),
+        Direction::Left => (row, (column + KEYPAD_COLUMNS - 1) % KEYPAD_COLUMNS),
+        Direction::Right => (row, (column + 1) % KEYPAD_COLUMNS),
+    };
+    row * KEYPAD_COLUMNS + column
+}
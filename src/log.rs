@@ -0,0 +1,9 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Headless runs have nowhere to draw, so what the window would show goes to stderr
+// instead, for the service manager to keep
+pub fn log(message: &str) {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    let (hours, minutes, seconds) = (seconds / 3600 % 24, seconds / 60 % 60, seconds % 60);
+    eprintln!("{:02}:{:02}:{:02} {}", hours, minutes, seconds, message);
+}
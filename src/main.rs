@@ -7,32 +7,77 @@ use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, MouseEvent, Mouse
 use std::io;
 
 mod mfd_keys;
-use mfd_keys::{press_osb, release_osb};
+mod joystick;
+mod output;
+use output::{press_osb, release_osb, ChordId};
 
 mod ui;
 use ui::Ui;
 
 #[cfg(test)]
 mod tests;
+#[cfg(windows)]
 mod winstance;
+mod instance;
 mod sound;
-use sound::{ClickSound, play_click};
+use sound::{Cue, voice};
 
 mod config;
-use config::{CONFIG, save_config, load_config, save_mfd_state};
+use config::{CONFIG, Config, ButtonAction, SelectionMode, RepeatGesture, ShiftLayer, HatRole, save_config, load_config, save_mfd_state, selection_mode, repeat_gesture, shift_layer, keypad_long_press, profile_long_press, cursor_position, save_cursor_position};
 
 mod osb;
-use osb::{calculate_osb_number, could_lead_to_valid_osb};
+use osb::{Selection, osb_number};
 
 mod direction;
 use direction::Direction;
 
+mod history;
+
+mod profile;
+use profile::Behaviour;
+
+mod rocker;
+use rocker::Rocker;
+
+mod keypad;
+use keypad::{KEYPAD_KEYS, KEYPAD_HOME};
+
+mod keyboard;
+use keyboard::KEYBOARD_DEVICE;
+
+mod midi;
+use midi::MIDI_DEVICE;
+
+mod binding;
+use binding::BindingWizard;
+
+mod detect;
+
+mod cli;
+mod control;
+mod log;
+mod keyfiles;
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 enum MfdState {
     LeftMfd,
     RightMfd,
 }
 
+impl MfdState {
+    pub fn index(&self) -> usize {
+        match self {
+            MfdState::LeftMfd => 0,
+            MfdState::RightMfd => 1,
+        }
+    }
+
+    // The MFD an OSB (1-40) is on
+    pub fn of_osb(osb_number: u8) -> MfdState {
+        if osb_number <= 20 { MfdState::LeftMfd } else { MfdState::RightMfd }
+    }
+}
+
 #[derive(Debug)]
 enum AppState {
     WaitingForSide {
@@ -47,19 +92,71 @@ enum AppState {
     OSBPressed {
         mfd: MfdState,
         osb_number: u8,
+        pressed_at: Instant,
+        repeats: u32,     // Auto-repeat half-pulses sent so far; the key is up while this is odd
+        down: bool,       // The OSB went down with this press and is ours to release
+        alternate: bool,  // A long press has sent the alternate chord instead
     },
     InvalidSequence {
         mfd: MfdState,
     },
-    BindingMode {
-        waiting_for: Direction,
+    CursorRoaming {
+        mfd: MfdState,
+        position: u8,  // 0-19, clockwise from the top left
+    },
+    SelectingRocker {
+        mfd: MfdState,
+        inputs: Vec<Direction>,
+        last_input_time: Instant,
+    },
+    RockerPressed {
+        mfd: MfdState,
+        rocker: Rocker,
+        up: bool,
+    },
+    Keypad {
+        mfd: MfdState,   // Where to go back to
+        position: u8,    // Index into KEYPAD_KEYS
+        pressed: bool,
+        underneath: Option<Box<AppState>>,  // What the shift layer was opened over
+    },
+    MacroLayer {
+        pressed: Option<Direction>,
+        underneath: Option<Box<AppState>>,
+    },
+    Binding(BindingWizard),
+    Learning {
+        mfd: MfdState,  // Where to go back to
+        next: String,   // What the next unbound press goes to, e.g. "MIDI 3"
     },
 }
 
+// A hat bound on top of the main one, with its own state machine
+struct Hat {
+    state: AppState,
+    long_press_detected: bool,
+}
+
+impl Hat {
+    fn new(role: HatRole) -> Self {
+        let state = match role {
+            HatRole::LeftMfd => idle_state(MfdState::LeftMfd),
+            HatRole::RightMfd => idle_state(MfdState::RightMfd),
+            HatRole::Keypad => AppState::Keypad { mfd: MfdState::LeftMfd, position: KEYPAD_HOME, pressed: false, underneath: None },
+        };
+        Hat { state, long_press_detected: false }
+    }
+}
+
 static SOUND_ENABLED: Mutex<bool> = Mutex::new(true);
 
 const TIMEOUT_DURATION: Duration = Duration::from_millis(1500);
 const LONGPRESS_DURATION: Duration = Duration::from_millis(500);
+const DOUBLE_TAP_DURATION: Duration = Duration::from_millis(300);
+const TAP_DURATION: Duration = Duration::from_millis(100);
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_millis(500);  // How often to look for edits to the config
+const PROFILE_CHECK_INTERVAL: Duration = Duration::from_secs(2);     // How often to look for a sim or aircraft change
+const MAX_SLEEP: Duration = Duration::from_millis(100);               // Longest the main loop waits between polls
 
 enum InputEventType {
     ButtonDown,    // When button is first pressed
@@ -79,40 +176,73 @@ fn handle_input_event(
         None => return, // Invalid button index
     };
 
+    handle_direction_event(event_type, direction, app_state, long_press_detected);
+}
+
+// Extra hats always drive the same display, so they can't switch it with a long press sideways
+fn handle_fixed_hat_event(event_type: InputEventType, direction: Direction, app_state: &mut AppState, long_press_detected: bool) {
+    if let (InputEventType::LongPress, Direction::Left | Direction::Right) = (&event_type, direction) {
+        return;
+    }
+    handle_direction_event(event_type, direction, app_state, long_press_detected);
+}
+
+fn handle_direction_event(
+    event_type: InputEventType,
+    direction: Direction,
+    app_state: &mut AppState,
+    long_press_detected: bool,
+) {
     match (event_type, &*app_state) {
         // Handle long press for MFD selection
-        (InputEventType::LongPress, AppState::WaitingForSide { .. }) => {
-            if let Direction::Left | Direction::Right = direction {
-                let selected_mfd = match direction {
-                    Direction::Left => {
-                        if *SOUND_ENABLED.lock().unwrap() {
-                            play_click(ClickSound::Left);
-                        }
-                        MfdState::LeftMfd
-                    },
-                    Direction::Right => {
-                        if *SOUND_ENABLED.lock().unwrap() {
-                            play_click(ClickSound::Right);
-                        }
-                        MfdState::RightMfd
-                    },
-                    _ => unreachable!(),
-                };
-                
-                // Save MFD state to config
-                save_mfd_state(selected_mfd.clone());
-                
-                *app_state = AppState::WaitingForSide {
-                    mfd: selected_mfd,
-                };
+        // ... and long press down for the corner rockers
+        (InputEventType::LongPress, AppState::WaitingForSide { mfd }) => {
+            match direction {
+                Direction::Left | Direction::Right => select_mfd(direction, app_state),
+                Direction::Down => {
+                    *app_state = AppState::SelectingRocker {
+                        mfd: mfd.clone(),
+                        inputs: Vec::new(),
+                        last_input_time: Instant::now(),
+                    };
+                }
+                // Profiles are cycled from check_long_presses, which can tell the UI
+                Direction::Up => {}
             }
         },
-        // Handle button releases in WaitingForSide state - ONLY if no long press was detected
-        (InputEventType::ButtonUp, AppState::WaitingForSide { .. }) => {
-            if !long_press_detected {
+        // In cursor mode a long press sideways still picks the MFD, up/down presses the OSB
+        (InputEventType::LongPress, AppState::CursorRoaming { .. }) => {
+            match direction {
+                Direction::Left | Direction::Right => select_mfd(direction, app_state),
+                Direction::Up | Direction::Down => press_cursor_osb(app_state),
+            }
+        },
+        (InputEventType::ButtonUp, AppState::CursorRoaming { .. }) if !long_press_detected => {
+            handle_short_press(direction, app_state);
+        },
+        (InputEventType::ButtonDown, AppState::CursorRoaming { .. }) => {},
+        // The keypad works the same way, with a long press sideways going back to an MFD
+        (InputEventType::LongPress, AppState::Keypad { .. }) => {
+            match direction {
+                Direction::Left | Direction::Right => select_mfd(direction, app_state),
+                Direction::Up | Direction::Down => press_keypad_key(app_state),
+            }
+        },
+        (InputEventType::ButtonUp, AppState::Keypad { pressed, .. }) => {
+            if *pressed {
+                handle_release(app_state);
+            } else if !long_press_detected {
                 handle_short_press(direction, app_state);
             }
         },
+        (InputEventType::ButtonDown, AppState::Keypad { .. }) => {},
+        // The macro layer holds a chord for as long as its direction is held
+        (InputEventType::ButtonDown, AppState::MacroLayer { .. }) => press_macro(direction, app_state),
+        (InputEventType::LongPress, AppState::MacroLayer { .. }) => {},
+        // Handle button releases in WaitingForSide state - ONLY if no long press was detected
+        (InputEventType::ButtonUp, AppState::WaitingForSide { .. }) if !long_press_detected => {
+            handle_short_press(direction, app_state);
+        },
         // Ignore button down events in WaitingForSide state to prevent accidental triggers
         (InputEventType::ButtonDown, AppState::WaitingForSide { .. }) => {},
         // Rest of the cases remain the same
@@ -129,10 +259,331 @@ fn handle_input_event(
     }
 }
 
+fn select_mfd(direction: Direction, app_state: &mut AppState) {
+    let (selected_mfd, word, cue) = match direction {
+        Direction::Left => (MfdState::LeftMfd, "left", Cue::MfdLeft),
+        Direction::Right => (MfdState::RightMfd, "right", Cue::MfdRight),
+        _ => return,
+    };
+
+    // Remember where the cursor was on the MFD we're leaving
+    if let AppState::CursorRoaming { mfd, position } = app_state {
+        save_cursor_position(mfd, *position);
+    }
+
+    // If it's turned on, long pressing towards the MFD that's already selected opens the ICP keypad
+    if let AppState::WaitingForSide { mfd } | AppState::CursorRoaming { mfd, .. } = app_state {
+        if *mfd == selected_mfd && keypad_long_press() {
+            *app_state = AppState::Keypad {
+                mfd: selected_mfd,
+                position: KEYPAD_HOME,
+                pressed: false,
+                underneath: None,
+            };
+            return;
+        }
+    }
+    announce(word, cue);
+
+    // Save MFD state to config
+    save_mfd_state(selected_mfd.clone());
+
+    *app_state = idle_state(selected_mfd);
+}
+
+// Every cue goes through here, so the [SOUND] button silences them all
+fn play_cue(cue: Cue) {
+    if *SOUND_ENABLED.lock().unwrap() {
+        sound::play(cue);
+    }
+}
+
+// The word from the voice pack, or the cue if there's no pack or it doesn't have the word
+fn announce(word: &str, cue: Cue) {
+    if *SOUND_ENABLED.lock().unwrap() {
+        sound::announce(word, cue);
+    }
+}
+
+// Cue files are looked for next to the config
+fn load_sounds(config: &Config) -> Vec<String> {
+    let path = config::config_path();
+    sound::load(&config.sounds, path.parent().unwrap_or(std::path::Path::new(".")))
+}
+
+// The state to return to once an OSB is released or the MFD changes
+fn idle_state(mfd: MfdState) -> AppState {
+    match selection_mode() {
+        SelectionMode::Cursor => AppState::CursorRoaming {
+            position: cursor_position(&mfd),
+            mfd,
+        },
+        _ => AppState::WaitingForSide { mfd },
+    }
+}
+
+fn press_cursor_osb(app_state: &mut AppState) {
+    if let AppState::CursorRoaming { mfd, position } = app_state {
+        save_cursor_position(mfd, *position);
+        *app_state = fire_osb(mfd.clone(), osb_number(mfd, *position));
+    }
+}
+
+// Press an OSB and remember it; what happens from here until handle_release depends on
+// its behaviour in the profile
+fn fire_osb(mfd: MfdState, osb_num: u8) -> AppState {
+    // println!("OSB {} pressed", osb_num);
+    let down = match profile::behaviour(osb_num) {
+        Behaviour::Hold | Behaviour::Pulse(_) => {
+            press_osb(osb_num);
+            true
+        }
+        Behaviour::Toggle => {
+            if output::is_held(osb_num) {
+                release_osb(osb_num);
+            } else {
+                press_osb(osb_num);
+            }
+            false
+        }
+        // Wait to see whether this is a tap or a long press
+        Behaviour::LongPress { .. } => false,
+    };
+    history::record(&MfdState::of_osb(osb_num), osb_num);
+    announce(voice::osb_word(osb_num), Cue::Osb);
+    AppState::OSBPressed {
+        mfd,
+        osb_number: osb_num,
+        pressed_at: Instant::now(),
+        repeats: 0,
+        down,
+        alternate: false,
+    }
+}
+
+// Rockers have no default keys in BMS, so they only do something once the profile has a chord
+fn press_rocker(mfd: MfdState, rocker: Rocker, up: bool) -> AppState {
+    // A chord that won't parse was reported when the config loaded
+    if let Some(Ok(keys)) = profile::rocker_chord(&mfd, rocker, up).map(|chord| mfd_keys::parse_chord(&chord)) {
+        output::press_chord(ChordId::Rocker(mfd.clone(), rocker, up), keys);
+    }
+    AppState::RockerPressed { mfd, rocker, up }
+}
+
+// Keypad keys are held until the hat or confirm button is released
+fn press_keypad_key(app_state: &mut AppState) {
+    if let AppState::Keypad { position, pressed: pressed @ false, .. } = app_state {
+        let key = KEYPAD_KEYS[*position as usize];
+        if let Some(Ok(keys)) = profile::keypad_chord(key).map(|chord| mfd_keys::parse_chord(&chord)) {
+            output::press_chord(ChordId::Keypad(*position), keys);
+        }
+        *pressed = true;
+    }
+}
+
+fn press_macro(direction: Direction, app_state: &mut AppState) {
+    if let AppState::MacroLayer { pressed: pressed @ None, .. } = app_state {
+        if let Some(Ok(keys)) = profile::macro_chord(direction).map(|chord| mfd_keys::parse_chord(&chord)) {
+            output::press_chord(ChordId::Macro(direction), keys);
+        }
+        *pressed = Some(direction);
+    }
+}
+
+// The MFD the user is working on, for states that have one
+fn current_mfd(app_state: &AppState) -> Option<MfdState> {
+    match app_state {
+        AppState::WaitingForSide { mfd }
+        | AppState::SelectingOSB { mfd, .. }
+        | AppState::OSBPressed { mfd, .. }
+        | AppState::InvalidSequence { mfd }
+        | AppState::CursorRoaming { mfd, .. }
+        | AppState::SelectingRocker { mfd, .. }
+        | AppState::RockerPressed { mfd, .. }
+        | AppState::Keypad { mfd, .. } => Some(mfd.clone()),
+        _ => None,
+    }
+}
+
+// While the modifier is held the hat drives the shift layer. Whatever was going on
+// underneath is kept in the layer's state and picked up again when it's let go.
+fn handle_modifier(pressed: bool, app_state: &mut AppState) {
+    if pressed {
+        let Some(mfd) = current_mfd(app_state) else { return };
+        if let AppState::Keypad { underneath: Some(_), .. } = app_state {
+            return;
+        }
+        // Don't leave anything held down underneath
+        handle_release(app_state);
+        let Some(layer) = shift_layer() else { return };
+        let layer = match layer {
+            ShiftLayer::Keypad => AppState::Keypad { mfd, position: KEYPAD_HOME, pressed: false, underneath: None },
+            ShiftLayer::Macros => AppState::MacroLayer { pressed: None, underneath: None },
+        };
+        let underneath = std::mem::replace(app_state, layer);
+        if let AppState::Keypad { underneath: layer, .. } | AppState::MacroLayer { underneath: layer, .. } = app_state {
+            *layer = Some(Box::new(underneath));
+        }
+    } else if let AppState::Keypad { underneath: Some(_), .. } | AppState::MacroLayer { underneath: Some(_), .. } = app_state {
+        handle_release(app_state);
+        if let AppState::Keypad { underneath, .. } | AppState::MacroLayer { underneath, .. } = app_state {
+            if let Some(underneath) = underneath.take() {
+                *app_state = *underneath;
+            }
+        }
+    }
+}
+
+// The state a shift layer was opened over, or the state itself
+fn underneath_layer(app_state: &mut AppState) -> &mut AppState {
+    match app_state {
+        AppState::Keypad { underneath: Some(underneath), .. }
+        | AppState::MacroLayer { underneath: Some(underneath), .. } => underneath,
+        state => state,
+    }
+}
+
+// Press an entry from the history if we're not in the middle of something else. Letting go
+// goes back to the MFD we were on, even if the OSB is on the other one.
+fn replay_osb(osb_num: u8, app_state: &mut AppState) {
+    if let AppState::WaitingForSide { mfd } | AppState::CursorRoaming { mfd, .. } = app_state {
+        *app_state = fire_osb(mfd.clone(), osb_num);
+    }
+}
+
+// Buttons bound straight to the MFDs act for as long as they're held, like the button itself
+fn handle_button_action(pressed: bool, chord_id: ChordId, action: &ButtonAction, app_state: &mut AppState) {
+    match action {
+        ButtonAction::Osb(osb_num @ 1..=40) => {
+            if pressed {
+                replay_osb(*osb_num, app_state);
+            } else if let AppState::OSBPressed { osb_number, .. } = app_state {
+                if osb_number == osb_num {
+                    handle_release(app_state);
+                }
+            }
+        }
+        // Reported when the config loaded
+        ButtonAction::Osb(_) => {}
+        ButtonAction::SelectMfd(mfd) => {
+            // Not while something is held or a shift layer is up
+            if pressed && current_mfd(app_state).is_some() && !is_holding(app_state) {
+                save_mfd_state(mfd.clone());
+                *app_state = idle_state(mfd.clone());
+            }
+        }
+        ButtonAction::Macro(chord) => {
+            if !pressed {
+                output::release_chord(chord_id);
+                return;
+            }
+            if let Ok(keys) = mfd_keys::parse_chord(chord) {
+                output::press_chord(chord_id, keys);
+            }
+        }
+    }
+}
+
+// The action mapped to a MIDI message
+fn midi_action(button_id: u32) -> Option<(usize, ButtonAction)> {
+    let config_lock = CONFIG.lock().ok()?;
+    let config = config_lock.as_ref()?;
+    let index = config.midi.iter().position(|mapping| mapping.matches(button_id))?;
+    Some((index, config.midi[index].action.clone()))
+}
+
+// Hands an unmapped MIDI message to the first mapping still waiting for one
+fn learn_midi(button_id: u32) -> bool {
+    let Ok(mut config_lock) = CONFIG.lock() else { return false };
+    let Some(config) = config_lock.as_mut() else { return false };
+    if config.midi.iter().any(|mapping| mapping.matches(button_id)) {
+        return false;
+    }
+    let Some(index) = config.midi.iter().position(|mapping| mapping.is_learning()) else { return false };
+    config.midi[index].learn(button_id);
+    save_config(config);
+    true
+}
+
+// The first mapping still waiting to be learned, named the way config problems name it
+fn next_to_learn() -> Option<String> {
+    let config_lock = CONFIG.lock().ok()?;
+    let config = config_lock.as_ref()?;
+    if let Some(index) = config.midi.iter().position(|mapping| mapping.is_learning()) {
+        return Some(format!("MIDI {}", index + 1));
+    }
+    let index = config.direct_buttons.iter().position(|direct| direct.button.is_none())?;
+    Some(format!("Direct button {}", index + 1))
+}
+
+// Learning only happens when asked for, so a stray press in the middle of a flight can't
+// take a mapping
+fn enter_learn_mode(app_state: &mut AppState, ui: &mut Ui) {
+    let Some(mfd) = current_mfd(app_state) else { return };
+    let Some(next) = next_to_learn() else {
+        ui.set_message("Nothing is waiting to be learned".to_string(), false);
+        return;
+    };
+    handle_release(app_state);
+    *app_state = AppState::Learning { mfd, next };
+}
+
+// The next unbound press is learned, until nothing is left waiting
+fn handle_learning(pressed: bool, device_id: u32, button_id: u32, app_state: &mut AppState) {
+    let AppState::Learning { mfd, .. } = app_state else { return };
+    if !pressed || is_bound_button(device_id, button_id) {
+        return;
+    }
+    let learned = if device_id == MIDI_DEVICE {
+        learn_midi(button_id)
+    } else {
+        learn_direct(device_id, button_id)
+    };
+    if !learned {
+        return;
+    }
+    play_cue(Cue::BindingStep);
+    *app_state = match next_to_learn() {
+        Some(next) => AppState::Learning { mfd: mfd.clone(), next },
+        None => idle_state(mfd.clone()),
+    };
+}
+
+// The same for direct buttons
+fn direct_action(device_id: u32, button_id: u32) -> Option<(usize, ButtonAction)> {
+    let config_lock = CONFIG.lock().ok()?;
+    let buttons = &config_lock.as_ref()?.direct_buttons;
+    let index = buttons.iter().position(|direct| direct.button == Some((device_id, button_id)))?;
+    Some((index, buttons[index].action.clone()))
+}
+
+// Callers check the button isn't bound to anything first
+fn learn_direct(device_id: u32, button_id: u32) -> bool {
+    let Ok(mut config_lock) = CONFIG.lock() else { return false };
+    let Some(config) = config_lock.as_mut() else { return false };
+    let Some(index) = config.direct_buttons.iter().position(|direct| direct.button.is_none()) else { return false };
+    config.direct_buttons[index].button = Some((device_id, button_id));
+    save_config(config);
+    true
+}
+
+// A dedicated confirm button presses the OSB under the cursor for as long as it's held
+fn handle_confirm(pressed: bool, app_state: &mut AppState) {
+    if pressed {
+        match app_state {
+            AppState::Keypad { .. } => press_keypad_key(app_state),
+            _ => press_cursor_osb(app_state),
+        }
+    } else {
+        handle_release(app_state);
+    }
+}
+
 fn handle_short_press(direction: Direction, app_state: &mut AppState) {
     match app_state {
         AppState::WaitingForSide { mfd } => {
             // println!("Side Selected: {:?}", direction);
+            announce(voice::side_word(direction), Cue::Side);
             *app_state = AppState::SelectingOSB {
                 mfd: mfd.clone(),
                 side: direction,
@@ -141,27 +592,76 @@ fn handle_short_press(direction: Direction, app_state: &mut AppState) {
             };
         }
         AppState::SelectingOSB { mfd, side, inputs, last_input_time } => {
+            // Quickly tapping the side of the last OSB again repeats it
+            if inputs.is_empty()
+                && direction == *side
+                && last_input_time.elapsed() < DOUBLE_TAP_DURATION
+                && repeat_gesture() == RepeatGesture::DoubleTap
+            {
+                let side = *side;
+                if let Some(last) = history::last(mfd).filter(|osb| osb::side_of((osb - 1) % 20) == side) {
+                    *app_state = fire_osb(mfd.clone(), last);
+                    return;
+                }
+            }
+
             *last_input_time = Instant::now();
             inputs.push(direction);
-            
-            if let Some(osb_num) = calculate_osb_number(mfd.clone(), *side, inputs.as_slice()) {
-                // println!("OSB {} pressed", osb_num);
-                press_osb(osb_num);
-                *app_state = AppState::OSBPressed {
-                    mfd: mfd.clone(),
-                    osb_number: osb_num,
+
+            match osb::strategy(selection_mode()).select(*side, inputs.as_slice()) {
+                Selection::Complete(index) => {
+                    *app_state = fire_osb(mfd.clone(), osb_number(mfd, index));
+                }
+                Selection::Invalid => {
+                    // println!("Invalid sequence detected. Resetting to side selection.");
+                    play_cue(Cue::Invalid);
+                    *app_state = AppState::InvalidSequence {
+                        mfd: mfd.clone(),
+                    };
+                }
+                Selection::Partial(_) => play_cue(Cue::Moved),
+            }
+        }
+        AppState::CursorRoaming { mfd, position } => {
+            *position = osb::move_cursor(*position, direction);
+            announce(voice::osb_word(osb_number(mfd, *position)), Cue::Moved);
+        }
+        AppState::Keypad { position, .. } => {
+            *position = keypad::move_keypad_cursor(*position, direction);
+            play_cue(Cue::Moved);
+        }
+        AppState::SelectingRocker { mfd, inputs, last_input_time } => {
+            // Once the corner is picked, up or down rocks it until released
+            if let Some(rocker) = rocker::select_rocker(inputs) {
+                *app_state = match direction {
+                    Direction::Up | Direction::Down => {
+                        play_cue(Cue::Osb);
+                        press_rocker(mfd.clone(), rocker, direction == Direction::Up)
+                    }
+                    _ => {
+                        play_cue(Cue::Invalid);
+                        AppState::InvalidSequence { mfd: mfd.clone() }
+                    }
                 };
-            } else if !could_lead_to_valid_osb(*side, inputs.as_slice()) {
-                // println!("Invalid sequence detected. Resetting to side selection.");
+                return;
+            }
+
+            *last_input_time = Instant::now();
+            inputs.push(direction);
+            if rocker::could_lead_to_rocker(inputs) {
+                play_cue(Cue::Moved);
+            } else {
+                play_cue(Cue::Invalid);
                 *app_state = AppState::InvalidSequence {
                     mfd: mfd.clone(),
                 };
             }
         }
-        AppState::OSBPressed { .. } | AppState::InvalidSequence { .. } => {
+        AppState::OSBPressed { .. } | AppState::RockerPressed { .. } | AppState::InvalidSequence { .. } => {
             // Ignore inputs while button is pressed or in invalid sequence state
         }
-        AppState::BindingMode { .. } => {
+        AppState::MacroLayer { .. } | AppState::Learning { .. } => {}
+        AppState::Binding(_) => {
             // Ignore short presses while in binding mode
         }
     }
@@ -169,92 +669,534 @@ fn handle_short_press(direction: Direction, app_state: &mut AppState) {
 
 fn handle_release(app_state: &mut AppState) {
     match app_state {
-        AppState::OSBPressed { mfd, osb_number: button_number } => {
+        AppState::OSBPressed { mfd, osb_number: button_number, pressed_at, repeats, down, alternate } => {
             // println!("OSB {} released", button_number);
-            release_osb(*button_number);
-            *app_state = AppState::WaitingForSide {
-                mfd: mfd.clone(),
-            };
+            match profile::behaviour(*button_number) {
+                Behaviour::Hold => {
+                    if *down && repeats.is_multiple_of(2) {
+                        release_osb(*button_number);
+                    }
+                }
+                Behaviour::Pulse(length) => {
+                    // Let go of the hat early and the pulse still runs its full length
+                    if *down {
+                        output::release_osb_at(*button_number, *pressed_at + length);
+                    }
+                }
+                Behaviour::Toggle => {}
+                Behaviour::LongPress { .. } => {
+                    if *alternate {
+                        output::release_chord(ChordId::Osb(*button_number));
+                    } else {
+                        press_osb(*button_number);
+                        output::release_osb_at(*button_number, Instant::now() + TAP_DURATION);
+                    }
+                }
+            }
+            *app_state = idle_state(mfd.clone());
+        }
+        AppState::RockerPressed { mfd, rocker, up } => {
+            output::release_chord(ChordId::Rocker(mfd.clone(), *rocker, *up));
+            *app_state = idle_state(mfd.clone());
+        }
+        AppState::Keypad { position, pressed: pressed @ true, .. } => {
+            output::release_chord(ChordId::Keypad(*position));
+            *pressed = false;
+        }
+        AppState::MacroLayer { pressed, .. } => {
+            if let Some(direction) = pressed.take() {
+                output::release_chord(ChordId::Macro(direction));
+            }
         }
         AppState::InvalidSequence { mfd } => {
             // Reset to waiting for side after handling release
-            *app_state = AppState::WaitingForSide {
-                mfd: mfd.clone(),
-            };
+            *app_state = idle_state(mfd.clone());
         }
         _ => {}
     }
 }
 
+// Swap in a config that was edited while we're running. Anything held is let go under the
+// old settings first, since the new ones might release it differently, and states that
+// belong to a selection mode or hat that has gone are reset.
+fn reload_config(config: Config, app_state: &mut AppState, inputs: &mut Inputs, midi: &mut Option<midi::MidiSource>) -> Result<(), String> {
+    let config = config::check_reload(config)?;
+    release_held(app_state, &mut inputs.hats);
+    let Some(old) = CONFIG.lock().unwrap().replace(config.clone()) else { return Ok(()) };
+    *SOUND_ENABLED.lock().unwrap() = config.sound_enabled;
+    let mut problems = Vec::new();
+    if old.sounds != config.sounds {
+        problems.extend(load_sounds(&config));
+    }
+
+    if !old.extra_hats.iter().map(|hat| hat.role).eq(config.extra_hats.iter().map(|hat| hat.role)) {
+        inputs.hats = config.extra_hats.iter().map(|hat| Hat::new(hat.role)).collect();
+    }
+    if old.selection_mode != config.selection_mode {
+        let states = std::iter::once(&mut *app_state)
+            .chain(inputs.hats.iter_mut().map(|hat| &mut hat.state))
+            .map(underneath_layer);
+        for state in states {
+            if let AppState::WaitingForSide { mfd }
+            | AppState::SelectingOSB { mfd, .. }
+            | AppState::InvalidSequence { mfd }
+            | AppState::CursorRoaming { mfd, .. } = state {
+                *state = idle_state(mfd.clone());
+            }
+        }
+    }
+
+    if old.output_mode != config.output_mode {
+        if let Err(err) = output::init(config.output_mode) {
+            problems.push(format!("output: {}", err));
+        }
+    }
+    if old.midi_device != config.midi_device {
+        *midi = None;
+        if let Some(device) = config.midi_device {
+            match midi::open(device) {
+                Ok(source) => *midi = Some(source),
+                Err(err) => problems.push(format!("MIDI: {}", err)),
+            }
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Reloaded config, but {}", problems.join(", ")))
+    }
+}
+
+// Keys held under the old key table are let go first, as the new one may not have them
+fn switch_profile(name: Option<String>) {
+    let changed = CONFIG.lock().unwrap().as_ref().is_some_and(|config| config.active_profile != name);
+    if !changed {
+        return;
+    }
+    output::release_all();
+    if let Ok(mut config_lock) = CONFIG.lock() {
+        if let Some(config) = config_lock.as_mut() {
+            config.active_profile = name;
+            save_config(config);
+        }
+    }
+}
+
+fn cycle_profile() {
+    let next = CONFIG.lock().unwrap().as_ref().and_then(|config| config.next_profile());
+    switch_profile(next);
+}
+
+// Moving on to the next profile by hand, from the keyboard, the window or the hat
+fn step_profile(app_state: &mut AppState, hats: &mut [Hat], ui: &mut Ui) {
+    release_held(app_state, hats);
+    cycle_profile();
+    let name = CONFIG.lock().unwrap().as_ref().map_or(String::new(), |config| config.profile_name().to_string());
+    ui.set_message(format!("Profile: {}", name), false);
+}
+
+// Let go of whatever the hats are holding, e.g. before the settings change under them
+fn release_held(app_state: &mut AppState, hats: &mut [Hat]) {
+    for state in std::iter::once(app_state).chain(hats.iter_mut().map(|hat| &mut hat.state)) {
+        if is_holding(state) {
+            handle_release(state);
+        }
+    }
+}
+
+// Answers a line from the control socket
+fn handle_request(request: control::Request, app_state: &mut AppState, inputs: &mut Inputs, midi: &mut Option<midi::MidiSource>, ui: &mut Ui, running: &mut bool) -> String {
+    let profile_name = || CONFIG.lock().unwrap().as_ref().map_or(String::new(), |config| config.profile_name().to_string());
+    match request {
+        control::Request::Press(osb_num) => {
+            press_osb(osb_num);
+            output::release_osb_at(osb_num, Instant::now() + TAP_DURATION);
+            format!("Pressed OSB {}", osb_num)
+        }
+        control::Request::Profile(Some(name)) => {
+            let Some(mut config) = CONFIG.lock().unwrap().clone() else { return "No config".to_string() };
+            if let Err(err) = cli::select_profile(&mut config, &name) {
+                return err;
+            }
+            release_held(app_state, &mut inputs.hats);
+            switch_profile(config.active_profile);
+            format!("Profile {}", profile_name())
+        }
+        control::Request::Profile(None) => format!("Profile {}", profile_name()),
+        control::Request::Reload => {
+            match config::reread_config(&config::config_path()).and_then(|config| reload_config(config, app_state, inputs, midi)) {
+                Ok(()) => "Reloaded config".to_string(),
+                Err(err) => err,
+            }
+        }
+        control::Request::Status => format!("{}: {}", profile_name(), ui::status_text(app_state)),
+        control::Request::Quit => {
+            *running = false;
+            "Stopping".to_string()
+        }
+        control::Request::Show => {
+            ui.set_message("Superhat was started again; this one is still running".to_string(), false);
+            "Superhat is already running".to_string()
+        }
+    }
+}
+
 fn check_for_timeouts(app_state: &mut AppState, ui: &mut Ui) -> io::Result<()> {
-    if let AppState::SelectingOSB { last_input_time, mfd, .. } = app_state {
+    if check_for_timeout(app_state) {
+        ui.update(app_state)?;
+    }
+    Ok(())
+}
+
+fn check_for_timeout(app_state: &mut AppState) -> bool {
+    if let AppState::SelectingOSB { last_input_time, mfd, .. } | AppState::SelectingRocker { last_input_time, mfd, .. } = app_state {
         if last_input_time.elapsed() > TIMEOUT_DURATION {
             //  println!("Timeout occurred. Resetting to side selection.");
+            play_cue(Cue::Timeout);
             *app_state = AppState::WaitingForSide {
                 mfd: mfd.clone(),
             };
-            ui.update(&app_state)?;
+            return true;
+        }
+    }
+    false
+}
+
+// Something is held down that the hat's release has to let go of, even after a long press
+fn is_holding(app_state: &AppState) -> bool {
+    matches!(app_state, AppState::OSBPressed { .. } | AppState::RockerPressed { .. } | AppState::Keypad { pressed: true, .. } | AppState::MacroLayer { pressed: Some(_), .. })
+}
+
+// Anything that happens while an OSB is held: pulses ending, long presses kicking in and
+// auto-repeat. Returns true if the state changed.
+fn check_held_osb(app_state: &mut AppState) -> bool {
+    let AppState::OSBPressed { osb_number, pressed_at, repeats, down, alternate, .. } = app_state else { return false };
+    match profile::behaviour(*osb_number) {
+        Behaviour::Hold => check_for_repeats(*osb_number, *pressed_at, repeats),
+        Behaviour::Pulse(length) => {
+            if *down && pressed_at.elapsed() >= length {
+                release_osb(*osb_number);
+                *down = false;
+                return true;
+            }
+            false
+        }
+        Behaviour::Toggle => false,
+        Behaviour::LongPress { threshold, alternate: chord } => {
+            if *alternate || pressed_at.elapsed() < threshold {
+                return false;
+            }
+            if let Ok(keys) = mfd_keys::parse_chord(&chord) {
+                output::press_chord(ChordId::Osb(*osb_number), keys);
+            }
+            // Either way, releasing the hat shouldn't tap the OSB any more
+            *alternate = true;
+            true
         }
     }
-    Ok(())
+}
+
+// When check_held_osb next has something to do
+fn held_osb_deadline(app_state: &AppState) -> Option<Instant> {
+    let AppState::OSBPressed { osb_number, pressed_at, repeats, down, alternate, .. } = app_state else { return None };
+    match profile::behaviour(*osb_number) {
+        Behaviour::Hold => {
+            let repeat = profile::auto_repeat(*osb_number)?;
+            let half_interval = Duration::from_millis(repeat.interval_ms) / 2;
+            Some(*pressed_at + Duration::from_millis(repeat.delay_ms) + half_interval * *repeats)
+        }
+        Behaviour::Pulse(length) => Some(*pressed_at + length).filter(|_| *down),
+        Behaviour::Toggle => None,
+        Behaviour::LongPress { threshold, .. } => Some(*pressed_at + threshold).filter(|_| !*alternate),
+    }
+}
+
+// How long the main loop can sleep before a repeat, pulse or long press is due
+fn sleep_duration(app_state: &AppState, inputs: &Inputs, now: Instant) -> Duration {
+    std::iter::once(app_state)
+        .chain(inputs.hats.iter().map(|hat| &hat.state))
+        .filter_map(held_osb_deadline)
+        .chain(output::next_release())
+        .chain(inputs.button_press_times.values().map(|pressed_at| *pressed_at + LONGPRESS_DURATION))
+        .filter(|at| *at > now)
+        .map(|at| at - now)
+        .fold(MAX_SLEEP, Duration::min)
+}
+
+// While an auto-repeating OSB is held, pulse it up and down. Each half of the interval
+// is long enough for the sim to see, even if it only polls the key or button state.
+fn check_for_repeats(osb_number: u8, pressed_at: Instant, repeats: &mut u32) -> bool {
+    let Some(repeat) = profile::auto_repeat(osb_number) else { return false };
+
+    let Some(repeating_for) = pressed_at.elapsed().checked_sub(Duration::from_millis(repeat.delay_ms)) else {
+        return false;
+    };
+    let half_interval = Duration::from_millis(repeat.interval_ms).as_secs_f64() / 2.0;
+    let due = (repeating_for.as_secs_f64() / half_interval) as u32 + 1;
+
+    if *repeats >= due {
+        return false;
+    }
+
+    // If we fell behind, skip the missed pulses rather than bursting them out
+    let is_down = repeats.is_multiple_of(2);
+    let should_be_down = due.is_multiple_of(2);
+    if is_down && !should_be_down {
+        release_osb(osb_number);
+    } else if !is_down && should_be_down {
+        press_osb(osb_number);
+    }
+    *repeats = due;
+    true
 }
 
 fn enter_binding_mode(app_state: &mut AppState, ui: &mut Ui) -> io::Result<()> {
     // println!("Entering binding mode. Press the button you want to use for UP");
-    *app_state = AppState::BindingMode {
-        waiting_for: Direction::Up,
-    };
+    let Some(config) = CONFIG.lock().unwrap().clone() else { return Ok(()) };
+    handle_release(app_state);
+    let bindings = config.bindings().clone();
+    let mfd = current_mfd(app_state).unwrap_or(config.selected_mfd);
+    let cursor_mode = config.selection_mode == SelectionMode::Cursor;
+    let shift = config.shift_layer.is_some();
+    *app_state = AppState::Binding(BindingWizard::new(mfd, bindings, cursor_mode, shift));
     ui.update(app_state)?;
     Ok(())
 }
 
-fn handle_binding(button_id: u32, device_id: u32, app_state: &mut AppState, ui: &mut Ui) {
-    let AppState::BindingMode { waiting_for } = app_state else { return };
-    
-    let mut config_lock = match CONFIG.lock() {
-        Ok(guard) => guard,
-        Err(_) => return,
-    };
-    
-    let Some(config) = config_lock.as_mut() else { return };
-    
-    match waiting_for {
-        Direction::Up => {
-            config.button_bindings.up = (device_id, button_id);
-            *app_state = AppState::BindingMode { waiting_for: Direction::Right };
-            ui.update(app_state).unwrap();
-        },
-        Direction::Right => {
-            config.button_bindings.right = (device_id, button_id);
-            *app_state = AppState::BindingMode { waiting_for: Direction::Down };
-            ui.update(app_state).unwrap();
-        },
-        Direction::Down => {
-            config.button_bindings.down = (device_id, button_id);
-            *app_state = AppState::BindingMode { waiting_for: Direction::Left };
-            ui.update(app_state).unwrap();
+// Back out of binding mode, leaving the bindings and MFD as they were
+fn cancel_binding(app_state: &mut AppState) {
+    if let AppState::Binding(wizard) = app_state {
+        *app_state = idle_state(wizard.mfd.clone());
+    }
+}
+
+// How the binding wizard describes a button
+fn button_label(device_id: u32, button_id: u32, device_names: &HashMap<u32, String>) -> String {
+    match device_id {
+        KEYBOARD_DEVICE => format!("Key {}", keyboard::key_name(button_id)),
+        MIDI_DEVICE => midi::describe(button_id),
+        _ => match device_names.get(&device_id) {
+            Some(name) => format!("{} button {}", name, button_id),
+            None => format!("Device {} button {}", device_id, button_id),
         },
-        Direction::Left => {
-            config.button_bindings.left = (device_id, button_id);
-            save_config(&config);
-            *app_state = AppState::InvalidSequence { mfd: MfdState::LeftMfd };
+    }
+}
+
+// What a button is already used for outside the main hat, which the wizard doesn't rebind
+fn button_use(device_id: u32, button_id: u32) -> Option<String> {
+    if let Some((index, direction)) = map_button_to_extra_hat(device_id, button_id) {
+        return Some(format!("hat {} {:?}", index + 2, direction));
+    }
+    if device_id == MIDI_DEVICE {
+        return midi_action(button_id).map(|(index, _)| format!("MIDI mapping {}", index + 1));
+    }
+    direct_action(device_id, button_id).map(|(index, _)| format!("direct button {}", index + 1))
+}
+
+fn handle_binding(pressed: bool, device_id: u32, button_id: u32, app_state: &mut AppState, device_names: &HashMap<u32, String>) {
+    let AppState::Binding(wizard) = app_state else { return };
+    if !pressed {
+        wizard.release((device_id, button_id));
+        return;
+    }
+
+    let label = button_label(device_id, button_id, device_names);
+    let taken = button_use(device_id, button_id);
+    let step = wizard.progress();
+    let finished = wizard.press((device_id, button_id), label, taken, Instant::now());
+    if finished || wizard.progress() != step {
+        play_cue(Cue::BindingStep);
+    }
+    if !finished {
+        return;
+    }
+
+    // Only now does anything change in the config
+    if let Ok(mut config_lock) = CONFIG.lock() {
+        if let Some(config) = config_lock.as_mut() {
+            *config.bindings_mut() = wizard.bindings.clone();
+            save_config(config);
+        }
+    }
+    *app_state = idle_state(wizard.mfd.clone());
+}
+
+// Per-button bookkeeping for the main loop, shared by every input source
+struct Inputs {
+    button_press_times: HashMap<(u32, u32), Instant>,
+    long_press_detected: bool,  // For the main hat; extra hats track their own
+    hats: Vec<Hat>,
+    device_names: HashMap<u32, String>,  // For the binding wizard
+}
+
+// A button going down or up, from a joystick, the keyboard or a MIDI controller
+fn process_button(pressed: bool, device_id: u32, button_id: u32, app_state: &mut AppState, inputs: &mut Inputs, ui: &mut Ui) {
+    if let AppState::Binding(_) = app_state {
+        handle_binding(pressed, device_id, button_id, app_state, &inputs.device_names);
+        ui.update(app_state).unwrap();
+        return;
+    }
+
+    if let AppState::Learning { .. } = app_state {
+        handle_learning(pressed, device_id, button_id, app_state);
+        ui.update(app_state).unwrap();
+        return;
+    }
+
+    if is_confirm_button(device_id, button_id) {
+        handle_confirm(pressed, app_state);
+        ui.update(app_state).unwrap();
+        return;
+    }
+
+    if is_modifier_button(device_id, button_id) {
+        handle_modifier(pressed, app_state);
+        ui.update(app_state).unwrap();
+        return;
+    }
+
+    let action = if device_id == MIDI_DEVICE {
+        midi_action(button_id).map(|(index, action)| (ChordId::Midi(index), action))
+    } else {
+        direct_action(device_id, button_id).map(|(index, action)| (ChordId::Direct(index), action))
+    };
+    if let Some((chord_id, action)) = action {
+        handle_button_action(pressed, chord_id, &action, app_state);
+        ui.update(app_state).unwrap();
+        return;
+    }
+
+    // Held keys repeat, but only the first press counts towards a long press
+    if pressed && inputs.button_press_times.contains_key(&(device_id, button_id)) {
+        return;
+    }
+
+    if let Some((index, direction)) = map_button_to_extra_hat(device_id, button_id) {
+        let hat = &mut inputs.hats[index];
+        if pressed {
+            inputs.button_press_times.insert((device_id, button_id), Instant::now());
+            hat.long_press_detected = false;
+            handle_fixed_hat_event(InputEventType::ButtonDown, direction, &mut hat.state, false);
+        } else {
+            inputs.button_press_times.remove(&(device_id, button_id));
+            if !hat.long_press_detected || is_holding(&hat.state) {
+                handle_fixed_hat_event(InputEventType::ButtonUp, direction, &mut hat.state, hat.long_press_detected);
+            }
+            hat.long_press_detected = false;
+        }
+        ui.set_extra_hats(inputs.hats.iter().map(|hat| &hat.state));
+        ui.update(app_state).unwrap();
+        return;
+    }
+
+    if map_button_to_direction(device_id, button_id).is_none() {
+        return;
+    }
+
+    if pressed {
+        inputs.button_press_times.insert((device_id, button_id), Instant::now());
+        inputs.long_press_detected = false; // Reset long press flag on new press
+        handle_input_event(InputEventType::ButtonDown, button_id, device_id, app_state, false);
+        ui.update(app_state).unwrap();
+    } else {
+        // Store the current long_press_detected state before removing from press_times
+        let was_long_press = inputs.long_press_detected;
+        if inputs.button_press_times.remove(&(device_id, button_id)).is_none() {
+            return;  // Pressed before binding finished
+        }
+
+        // Only process button release if it wasn't a long press or if something is pressed
+        if !was_long_press || is_holding(app_state) {
+            handle_input_event(InputEventType::ButtonUp, button_id, device_id, app_state, was_long_press);
             ui.update(app_state).unwrap();
-        },
+        }
+
+        if inputs.button_press_times.is_empty() {
+            inputs.long_press_detected = false;
+        }
+    }
+}
+
+fn check_long_presses(app_state: &mut AppState, inputs: &mut Inputs, ui: &mut Ui) {
+    for (&(device_id, button_id), &press_time) in inputs.button_press_times.iter() {
+        if let Some((index, direction)) = map_button_to_extra_hat(device_id, button_id) {
+            let hat = &mut inputs.hats[index];
+            if !hat.long_press_detected && press_time.elapsed() >= LONGPRESS_DURATION {
+                hat.long_press_detected = true;
+                handle_fixed_hat_event(InputEventType::LongPress, direction, &mut hat.state, true);
+                ui.set_extra_hats(inputs.hats.iter().map(|hat| &hat.state));
+                ui.update(app_state).unwrap();
+            }
+            continue;
+        }
+
+        if !inputs.long_press_detected && press_time.elapsed() >= LONGPRESS_DURATION {
+            if let Some(direction) = map_button_to_direction(device_id, button_id) {
+                inputs.long_press_detected = true;  // Set this before handling the event
+                // If it's turned on, a long press up before picking a side moves on to the next profile
+                if direction == Direction::Up && matches!(app_state, AppState::WaitingForSide { .. }) && profile_long_press() {
+                    step_profile(app_state, &mut inputs.hats, ui);
+                    ui.update(app_state).unwrap();
+                    continue;
+                }
+                handle_input_event(
+                    InputEventType::LongPress,
+                    button_id,
+                    device_id,
+                    app_state,
+                    true
+                );
+                ui.update(app_state).unwrap();
+            }
+        }
     }
 }
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    // Create UI first - this handles single instance check
-    let mut ui = match Ui::new() {
-        Ok(ui) => ui,
-        Err(e) => {
-            if e.kind() == io::ErrorKind::Other {
-                return Ok(()); // Exit quietly if another instance is running
-            }
-            return Err(e);    // Propagate other errors
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
         }
     };
+    if let Some(path) = &options.config {
+        config::set_config_path(path.clone());
+    }
+    if options.headless && options.command == cli::Command::Bind {
+        eprintln!("Binding needs the console, so it can't be done headless");
+        std::process::exit(2);
+    }
+
+    // Anything that sends keys goes to the Superhat that's already running, if there is one
+    let mut guard = None;
+    if let Some(line) = instance::forwarded_request(&options.command) {
+        match instance::claim()? {
+            instance::Claim::First(claimed) => guard = Some(claimed),
+            instance::Claim::Running => {
+                match instance::forward(&line) {
+                    Ok(reply) => println!("{}", reply),
+                    Err(err) => {
+                        eprintln!("Couldn't reach the running Superhat: {}", err);
+                        std::process::exit(1);
+                    }
+                }
+                return Ok(());
+            }
+        }
+    }
+    if !matches!(options.command, cli::Command::Run | cli::Command::Bind) {
+        if let Err(err) = cli::run_command(&options) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let mut guard = guard.expect("run and bind always claim the instance");
+
+    let mut ui = if options.headless { Ui::headless() } else { Ui::new()? };
+    control::watch_signals();
 
     let mut gilrs = Gilrs::new().unwrap();
 
@@ -267,29 +1209,84 @@ async fn main() -> io::Result<()> {
     }
 
     // Load config and check if controls are bound
-    let config = load_config();
-    let controls_bound = config.button_bindings.up != (0, 0) 
-        && config.button_bindings.right != (0, 0)
-        && config.button_bindings.down != (0, 0)
-        && config.button_bindings.left != (0, 0);
+    let mut config = load_config().unwrap_or_else(|err| {
+        ui.set_message(err, true);
+        Config::default()
+    });
+    if let Some(name) = &options.profile {
+        if let Err(err) = cli::select_profile(&mut config, name) {
+            ui.set_message(err, true);
+        }
+    }
+    if let Some(message) = config::problems_message(&config.problems()) {
+        ui.set_message(message, true);
+    }
+    // A button box mapped one-to-one doesn't need a hat
+    let controls_bound = (config.bindings().up != (0, 0) 
+        && config.bindings().right != (0, 0)
+        && config.bindings().down != (0, 0)
+        && config.bindings().left != (0, 0))
+        || !config.direct_buttons.is_empty();
 
     // Initialize sound state from config
     *SOUND_ENABLED.lock().unwrap() = config.sound_enabled;
+    // Clicks are nice to have, so no sound device just means no clicks
+    match sound::open() {
+        Ok(backend) => sound::set_backend(Some(backend)),
+        Err(err) => ui.set_message(err.to_string(), true),
+    }
+    for problem in load_sounds(&config) {
+        ui.set_message(problem, true);
+    }
 
     *CONFIG.lock().unwrap() = Some(config.clone());  // Clone if needed
+    if let Some(next) = next_to_learn() {
+        ui.set_message(format!("{} has nothing bound yet - press l to learn it", next), false);
+    }
 
-    let mut app_state = if !controls_bound {
-        AppState::BindingMode {
-            waiting_for: Direction::Up,
-        }
-    } else {
-        AppState::WaitingForSide {
-            mfd: config.selected_mfd,
-        }
-    };
+    // Set up the output backend - a missing vJoy/uinput device is fatal rather than
+    // silently falling back to keys the user has told us they don't want
+    output::init(options.output.unwrap_or(config.output_mode))?;
+
+    let mut app_state = idle_state(config.selected_mfd.clone());
+    if ui.is_headless() && !controls_bound {
+        ui.set_message("No hat is bound yet - run superhat bind first".to_string(), true);
+    } else if !controls_bound || options.command == cli::Command::Bind {
+        enter_binding_mode(&mut app_state, &mut ui)?;
+    }
     
-    let mut button_press_times: HashMap<(u32, u32), Instant> = HashMap::new();
-    let mut long_press_detected: bool = false;
+    let mut inputs = Inputs {
+        button_press_times: HashMap::new(),
+        long_press_detected: false,
+        hats: config.extra_hats.iter().map(|hat| Hat::new(hat.role)).collect(),
+        device_names: gilrs.gamepads()
+            .map(|(id, gamepad)| (u32::try_from(usize::from(id)).unwrap(), gamepad.name().to_string()))
+            .collect(),
+    };
+    ui.set_extra_hats(inputs.hats.iter().map(|hat| &hat.state));
+
+    let mut midi = match config.midi_device {
+        Some(device) => Some(midi::open(device)?),
+        None => None,
+    };
+
+    let mut config_watcher = config::ConfigWatcher::new(config::config_path());
+    let mut last_config_check = Instant::now();
+
+    // A port that's in use only costs us the telemetry, not the process detection
+    let mut detector = detect::ProfileDetector::default();
+    if let Err(err) = detector.listen(config.telemetry_port) {
+        ui.set_message(format!("Couldn't listen for telemetry: {}", err), true);
+    }
+    let mut last_profile_check = Instant::now() - PROFILE_CHECK_INTERVAL;
+
+    let mut control = None;
+    if let Some(port) = config.control_port {
+        match control::ControlServer::bind(port) {
+            Ok(server) => control = Some(server),
+            Err(err) => ui.set_message(format!("Couldn't listen for requests: {}", err), true),
+        }
+    }
 
     // flush any events that happened before we started
     std::thread::sleep(Duration::from_millis(100));
@@ -299,85 +1296,121 @@ async fn main() -> io::Result<()> {
     ui.update(&app_state)?;
     
     let mut running = true;
+    let mut mouse_osb = None;  // An OSB from the history, held for as long as the mouse button is
     while running {
         // Need to keep an eye on this blocking code - in some situations it blocks indefinitely but is
         // masked by axis events coming in causing it to carry through
         while let Some(GilrsEvent { id, event, .. }) = gilrs.next_event() {
+            let device_id = u32::try_from(usize::from(id)).unwrap();
             match event {
                 EventType::ButtonPressed(_, code) => {
-                    let button_id = code.into_u32();
-                    let device_id = u32::try_from(usize::from(id)).unwrap();
-
-                    if let AppState::BindingMode { .. } = app_state {
-                        handle_binding(button_id, device_id, &mut app_state, &mut ui);
-                        ui.update(&app_state).unwrap();
-                        continue;
-                    }
-
-                    if let Some(_) = map_button_to_direction(device_id, button_id) {
-                        button_press_times.insert((device_id, button_id), Instant::now());
-                        long_press_detected = false; // Reset long press flag on new press
-                        handle_input_event(InputEventType::ButtonDown, button_id, device_id, &mut app_state, long_press_detected);
-                        ui.update(&app_state).unwrap();
-                    }
+                    process_button(true, device_id, code.into_u32(), &mut app_state, &mut inputs, &mut ui);
                 }
                 EventType::ButtonReleased(_, code) => {
-                    let button_id = code.into_u32();
-                    let device_id = u32::try_from(usize::from(id)).unwrap();
-
-                    if let AppState::BindingMode { .. } = app_state {
-                        continue;
-                    }
-
-                    if let Some(_) = map_button_to_direction(device_id, button_id) {
-                        // Store the current long_press_detected state before removing from press_times
-                        let was_long_press = long_press_detected;
-                        button_press_times.remove(&(device_id, button_id));
-                        
-                        // Only process button release if it wasn't a long press or if we're in OSBPressed state
-                        if !was_long_press || matches!(app_state, AppState::OSBPressed { .. }) {
-                            handle_input_event(InputEventType::ButtonUp, button_id, device_id, &mut app_state, was_long_press);
-                            ui.update(&app_state).unwrap();
-                        }
-                        
-                        if button_press_times.is_empty() {
-                            long_press_detected = false;
-                        }
-                    }
+                    process_button(false, device_id, code.into_u32(), &mut app_state, &mut inputs, &mut ui);
+                }
+                EventType::Connected => {
+                    inputs.device_names.insert(device_id, gilrs.gamepad(id).name().to_string());
                 }
                 _ => {}
             }
         }
 
-        // Check for long presses on every iteration
-        for (&(device_id, button_id), &press_time) in button_press_times.iter() {
-            if !long_press_detected && press_time.elapsed() >= LONGPRESS_DURATION {
-                if let Some(_) = map_button_to_direction(device_id, button_id) {
-                    long_press_detected = true;  // Set this before handling the event
-                    handle_input_event(
-                        InputEventType::LongPress,
-                        button_id,
-                        device_id,
-                        &mut app_state,
-                        true
-                    );
-                    ui.update(&app_state).unwrap();
-                }
+        if let Some(midi) = midi.as_mut() {
+            for (button_id, pressed) in midi.poll()? {
+                process_button(pressed, MIDI_DEVICE, button_id, &mut app_state, &mut inputs, &mut ui);
             }
         }
 
-        // Process other events
-        while crossterm::event::poll(Duration::ZERO)? {
+        check_long_presses(&mut app_state, &mut inputs, &mut ui);
+
+        // Requests from the control socket and from later launches are answered the same way
+        let mut answer = |request| match request {
+            Ok(request) => handle_request(request, &mut app_state, &mut inputs, &mut midi, &mut ui, &mut running),
+            Err(err) => err,
+        };
+        let mut handled = guard.poll(&mut answer);
+        handled |= control.as_mut().is_some_and(|server| server.poll(&mut answer));
+        if handled {
+            ui.set_extra_hats(inputs.hats.iter().map(|hat| &hat.state));
+            ui.update(&app_state)?;
+        }
+        if control::take_reload_request() {
+            let result = config::reread_config(&config::config_path())
+                .and_then(|config| reload_config(config, &mut app_state, &mut inputs, &mut midi));
+            match result {
+                Ok(()) => ui.set_message("Reloaded config".to_string(), false),
+                Err(err) => ui.set_message(err, true),
+            }
+            ui.set_extra_hats(inputs.hats.iter().map(|hat| &hat.state));
+            ui.update(&app_state)?;
+        }
+        if control::stop_requested() {
+            running = false;
+        }
+
+        // Process other events; there's no terminal to read from when headless
+        while !ui.is_headless() && crossterm::event::poll(Duration::ZERO)? {
             match crossterm::event::read()? {
-                Event::Key(KeyEvent { code: KeyCode::Char(c), kind: KeyEventKind::Press, .. }) => {
-                    match c.to_ascii_lowercase() {
-                        'b' => {
-                            enter_binding_mode(&mut app_state, &mut ui)?;
+                Event::Key(KeyEvent { code, kind, .. }) => {
+                    // Keys that are bound (or being bound) act as buttons, ahead of the shortcuts
+                    let pressed = kind != KeyEventKind::Release;
+                    let binding = matches!(app_state, AppState::Binding(_));
+                    let reserved = [KeyCode::Char('q'), KeyCode::Backspace];
+                    let key = keyboard::key_id(code).filter(|&key| {
+                        (binding && !reserved.contains(&code)) || is_bound_button(KEYBOARD_DEVICE, key)
+                    });
+                    if let Some(key) = key {
+                        // Repeats the terminal marks as such can't confirm a binding
+                        if binding && kind == KeyEventKind::Repeat {
+                            continue;
                         }
-                        'q' => {
-                            running = false;
+                        if ui.reports_key_releases() {
+                            process_button(pressed, KEYBOARD_DEVICE, key, &mut app_state, &mut inputs, &mut ui);
+                        } else if let AppState::Binding(wizard) = &mut app_state {
+                            // A key bound to the hat would never come back up
+                            wizard.message = Some("This terminal doesn't report key releases, so keys can't be bound".to_string());
+                            ui.update(&app_state)?;
+                        } else {
+                            // Keys bound elsewhere can still be tapped
+                            process_button(true, KEYBOARD_DEVICE, key, &mut app_state, &mut inputs, &mut ui);
+                            process_button(false, KEYBOARD_DEVICE, key, &mut app_state, &mut inputs, &mut ui);
+                        }
+                        continue;
+                    }
+
+                    // Backspace steps back through the binding wizard and Esc leaves it
+                    if let (AppState::Binding(wizard), KeyEventKind::Press) = (&mut app_state, kind) {
+                        match code {
+                            KeyCode::Backspace => wizard.back(),
+                            KeyCode::Esc => cancel_binding(&mut app_state),
+                            _ => {}
+                        }
+                        ui.update(&app_state)?;
+                    }
+                    if let (AppState::Learning { mfd, .. }, KeyCode::Esc) = (&app_state, code) {
+                        app_state = idle_state(mfd.clone());
+                        ui.update(&app_state)?;
+                    }
+
+                    if let (KeyCode::Char(c), KeyEventKind::Press) = (code, kind) {
+                        match c.to_ascii_lowercase() {
+                            'b' => {
+                                enter_binding_mode(&mut app_state, &mut ui)?;
+                            }
+                            'l' => {
+                                enter_learn_mode(&mut app_state, &mut ui);
+                                ui.update(&app_state)?;
+                            }
+                            'p' => {
+                                step_profile(&mut app_state, &mut inputs.hats, &mut ui);
+                                ui.update(&app_state)?;
+                            }
+                            'q' => {
+                                running = false;
+                            }
+                            _ => {}
                         }
-                        _ => {}
                     }
                 }
                 Event::Resize(width, height) => {
@@ -388,11 +1421,8 @@ async fn main() -> io::Result<()> {
                         MouseEventKind::Down(_) => {
                             if ui.is_bind_button_click(column, row) {
                                 match app_state {
-                                    AppState::BindingMode { .. } => {
-                                        // Exit binding mode (TODO: don't reset the MFD)
-                                        app_state = AppState::WaitingForSide { 
-                                            mfd: MfdState::LeftMfd 
-                                        };
+                                    AppState::Binding(_) => {
+                                        cancel_binding(&mut app_state);
                                         ui.update(&app_state)?;
                                     },
                                     _ => {
@@ -400,6 +1430,9 @@ async fn main() -> io::Result<()> {
                                         enter_binding_mode(&mut app_state, &mut ui)?;
                                     }
                                 }
+                            } else if ui.is_profile_button_click(column, row) && !matches!(app_state, AppState::Binding(_)) {
+                                step_profile(&mut app_state, &mut inputs.hats, &mut ui);
+                                ui.update(&app_state)?;
                             } else if ui.is_sound_button_click(column, row) {
                                 // Scope the lock to ensure it's released before calling update
                                 {
@@ -410,11 +1443,26 @@ async fn main() -> io::Result<()> {
                                     if let Ok(mut config_lock) = CONFIG.lock() {
                                         if let Some(config) = config_lock.as_mut() {
                                             config.sound_enabled = *sound_enabled;
-                                            save_config(&config);
+                                            save_config(config);
                                         }
                                     }
                                 } // Lock is released here
                                 
+                                ui.update(&app_state)?;
+                            } else if let Some((_, osb_num)) = ui.history_entry_at(column, row) {
+                                // Held for as long as the mouse button is
+                                replay_osb(osb_num, &mut app_state);
+                                if let AppState::OSBPressed { osb_number, .. } = app_state {
+                                    mouse_osb = Some(osb_number);
+                                }
+                                ui.update(&app_state)?;
+                            }
+                        }
+                        // Only an OSB the mouse pressed, not one the hat is holding
+                        MouseEventKind::Up(_) => {
+                            let osb_num = mouse_osb.take();
+                            if matches!(app_state, AppState::OSBPressed { osb_number, .. } if Some(osb_number) == osb_num) {
+                                handle_release(&mut app_state);
                                 ui.update(&app_state)?;
                             }
                         }
@@ -426,8 +1474,59 @@ async fn main() -> io::Result<()> {
         }
 
         check_for_timeouts(&mut app_state, &mut ui)?;
-        std::thread::sleep(Duration::from_millis(100));
+        let mut changed = check_held_osb(&mut app_state);
+        for hat in inputs.hats.iter_mut() {
+            changed |= check_for_timeout(&mut hat.state);
+            changed |= check_held_osb(&mut hat.state);
+        }
+        if changed {
+            ui.set_extra_hats(inputs.hats.iter().map(|hat| &hat.state));
+            ui.update(&app_state)?;
+        }
+
+        if last_config_check.elapsed() >= CONFIG_CHECK_INTERVAL {
+            last_config_check = Instant::now();
+            if let Some(result) = config_watcher.poll() {
+                match result.and_then(|config| reload_config(config, &mut app_state, &mut inputs, &mut midi)) {
+                    Ok(()) => ui.set_message("Reloaded config".to_string(), false),
+                    Err(err) => ui.set_message(err, true),
+                }
+                ui.set_extra_hats(inputs.hats.iter().map(|hat| &hat.state));
+                ui.update(&app_state)?;
+            }
+        }
+
+        if last_profile_check.elapsed() >= PROFILE_CHECK_INTERVAL {
+            last_profile_check = Instant::now();
+            let config = CONFIG.lock().unwrap().clone().unwrap_or_default();
+            if detector.port() != config.telemetry_port {
+                if let Err(err) = detector.listen(config.telemetry_port) {
+                    ui.set_message(format!("Couldn't listen for telemetry: {}", err), true);
+                }
+            }
+            if control.as_ref().and_then(|server| server.port()) != config.control_port {
+                control = None;
+                if let Some(port) = config.control_port {
+                    match control::ControlServer::bind(port) {
+                        Ok(server) => control = Some(server),
+                        Err(err) => ui.set_message(format!("Couldn't listen for requests: {}", err), true),
+                    }
+                }
+            }
+            // Not while binding, as the wizard is working on the current profile's bindings
+            if let (Some(name), false) = (detector.poll(&config.profiles), matches!(app_state, AppState::Binding(_))) {
+                release_held(&mut app_state, &mut inputs.hats);
+                switch_profile(Some(name.clone()));
+                ui.set_message(format!("Switched to profile {}", name), false);
+                ui.set_extra_hats(inputs.hats.iter().map(|hat| &hat.state));
+                ui.update(&app_state)?;
+            }
+        }
+        output::tick();
+        std::thread::sleep(sleep_duration(&app_state, &inputs, Instant::now()));
     }
+
+    output::release_all();
     Ok(())
 }
 
@@ -435,10 +1534,10 @@ fn map_button_to_direction(device_id: u32, button_id: u32) -> Option<Direction>
     if let Ok(config) = CONFIG.lock() {
         if let Some(config) = config.as_ref() {
             let direction = match (device_id, button_id) {
-                (dev, code) if (dev, code) == config.button_bindings.up => Some(Direction::Up),
-                (dev, code) if (dev, code) == config.button_bindings.right => Some(Direction::Right),
-                (dev, code) if (dev, code) == config.button_bindings.down => Some(Direction::Down),
-                (dev, code) if (dev, code) == config.button_bindings.left => Some(Direction::Left),
+                (dev, code) if (dev, code) == config.bindings().up => Some(Direction::Up),
+                (dev, code) if (dev, code) == config.bindings().right => Some(Direction::Right),
+                (dev, code) if (dev, code) == config.bindings().down => Some(Direction::Down),
+                (dev, code) if (dev, code) == config.bindings().left => Some(Direction::Left),
                 _ => None,
             };
 
@@ -446,4 +1545,45 @@ fn map_button_to_direction(device_id: u32, button_id: u32) -> Option<Direction>
         }
     }
     None
-}
\ No newline at end of file
+}
+
+// Which extra hat, if any, a button belongs to
+fn map_button_to_extra_hat(device_id: u32, button_id: u32) -> Option<(usize, Direction)> {
+    let config = CONFIG.lock().ok()?;
+    config.as_ref()?.extra_hats.iter().enumerate().find_map(|(index, hat)| {
+        let direction = match (device_id, button_id) {
+            button if button == hat.up => Direction::Up,
+            button if button == hat.right => Direction::Right,
+            button if button == hat.down => Direction::Down,
+            button if button == hat.left => Direction::Left,
+            _ => return None,
+        };
+        Some((index, direction))
+    })
+}
+
+fn is_confirm_button(device_id: u32, button_id: u32) -> bool {
+    if let Ok(config) = CONFIG.lock() {
+        if let Some(config) = config.as_ref() {
+            return config.bindings().confirm == Some((device_id, button_id));
+        }
+    }
+    false
+}
+
+fn is_modifier_button(device_id: u32, button_id: u32) -> bool {
+    if let Ok(config) = CONFIG.lock() {
+        if let Some(config) = config.as_ref() {
+            return config.bindings().modifier == Some((device_id, button_id));
+        }
+    }
+    false
+}
+
+fn is_bound_button(device_id: u32, button_id: u32) -> bool {
+    map_button_to_direction(device_id, button_id).is_some()
+        || map_button_to_extra_hat(device_id, button_id).is_some()
+        || is_confirm_button(device_id, button_id)
+        || is_modifier_button(device_id, button_id)
+        || direct_action(device_id, button_id).is_some()
+}
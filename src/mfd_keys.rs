@@ -1,66 +1,88 @@
-use winky::Key;
+use std::io;
 
-// These are the default keys for the OSBs in BMS 4.37
-pub static MFD_KEYS: &[&[Key]] = &[
-    &[Key::Control, Key::Alt, Key::Num1],
-    &[Key::Control, Key::Alt, Key::Num2], 
-    &[Key::Control, Key::Alt, Key::Num3],
-    &[Key::Control, Key::Alt, Key::Num4],
-    &[Key::Control, Key::Alt, Key::Num5],
+#[cfg(windows)]
+pub use winky::Key;
 
-    &[Key::Control, Key::Alt, Key::Num6],
-    &[Key::Control, Key::Alt, Key::Num7],
-    &[Key::Control, Key::Alt, Key::Num8],
-    &[Key::Control, Key::Alt, Key::Num9],
-    &[Key::Control, Key::Alt, Key::Num0],
+// Only Windows can send keys, but chords are still checked everywhere
+#[cfg(not(windows))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Control, Alt, Shift,
+    Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+}
+
+// These are the default keys for the OSBs in BMS 4.37, written the same way as profile chords
+pub static MFD_KEYS: &[&str] = &[
+    "Ctrl+Alt+1", "Ctrl+Alt+2", "Ctrl+Alt+3", "Ctrl+Alt+4", "Ctrl+Alt+5",
+    "Ctrl+Alt+6", "Ctrl+Alt+7", "Ctrl+Alt+8", "Ctrl+Alt+9", "Ctrl+Alt+0",
 
-    &[Key::Control, Key::Alt, Key::Numpad1],
-    &[Key::Control, Key::Alt, Key::Numpad2], 
-    &[Key::Control, Key::Alt, Key::Numpad3],
-    &[Key::Control, Key::Alt, Key::Numpad4],
-    &[Key::Control, Key::Alt, Key::Numpad5],
-    
-    &[Key::Control, Key::Alt, Key::Numpad6],
-    &[Key::Control, Key::Alt, Key::Numpad7], 
-    &[Key::Control, Key::Alt, Key::Numpad8],
-    &[Key::Control, Key::Alt, Key::Numpad9],
-    &[Key::Control, Key::Alt, Key::Numpad0],
+    "Ctrl+Alt+Numpad1", "Ctrl+Alt+Numpad2", "Ctrl+Alt+Numpad3", "Ctrl+Alt+Numpad4", "Ctrl+Alt+Numpad5",
+    "Ctrl+Alt+Numpad6", "Ctrl+Alt+Numpad7", "Ctrl+Alt+Numpad8", "Ctrl+Alt+Numpad9", "Ctrl+Alt+Numpad0",
 
-    &[Key::Shift, Key::Alt, Key::Num1],
-    &[Key::Shift, Key::Alt, Key::Num2], 
-    &[Key::Shift, Key::Alt, Key::Num3],
-    &[Key::Shift, Key::Alt, Key::Num4],
-    &[Key::Shift, Key::Alt, Key::Num5],
+    "Shift+Alt+1", "Shift+Alt+2", "Shift+Alt+3", "Shift+Alt+4", "Shift+Alt+5",
+    "Shift+Alt+6", "Shift+Alt+7", "Shift+Alt+8", "Shift+Alt+9", "Shift+Alt+0",
 
-    &[Key::Shift, Key::Alt, Key::Num6],
-    &[Key::Shift, Key::Alt, Key::Num7],
-    &[Key::Shift, Key::Alt, Key::Num8],
-    &[Key::Shift, Key::Alt, Key::Num9],
-    &[Key::Shift, Key::Alt, Key::Num0],
+    "Shift+Alt+Numpad1", "Shift+Alt+Numpad2", "Shift+Alt+Numpad3", "Shift+Alt+Numpad4", "Shift+Alt+Numpad5",
+    "Shift+Alt+Numpad6", "Shift+Alt+Numpad7", "Shift+Alt+Numpad8", "Shift+Alt+Numpad9", "Shift+Alt+Numpad0",
+];
 
-    &[Key::Shift, Key::Alt, Key::Numpad1],
-    &[Key::Shift, Key::Alt, Key::Numpad2], 
-    &[Key::Shift, Key::Alt, Key::Numpad3],
-    &[Key::Shift, Key::Alt, Key::Numpad4],
-    &[Key::Shift, Key::Alt, Key::Numpad5],
-    
-    &[Key::Shift, Key::Alt, Key::Numpad6],
-    &[Key::Shift, Key::Alt, Key::Numpad7], 
-    &[Key::Shift, Key::Alt, Key::Numpad8],
-    &[Key::Shift, Key::Alt, Key::Numpad9],
-    &[Key::Shift, Key::Alt, Key::Numpad0],
+// Names accepted in key chords like "Ctrl+Alt+F1"
+static KEY_NAMES: &[(&str, Key)] = &[
+    ("Ctrl", Key::Control), ("Control", Key::Control), ("Alt", Key::Alt), ("Shift", Key::Shift),
+    ("0", Key::Num0), ("1", Key::Num1), ("2", Key::Num2), ("3", Key::Num3), ("4", Key::Num4),
+    ("5", Key::Num5), ("6", Key::Num6), ("7", Key::Num7), ("8", Key::Num8), ("9", Key::Num9),
+    ("Numpad0", Key::Numpad0), ("Numpad1", Key::Numpad1), ("Numpad2", Key::Numpad2), ("Numpad3", Key::Numpad3),
+    ("Numpad4", Key::Numpad4), ("Numpad5", Key::Numpad5), ("Numpad6", Key::Numpad6), ("Numpad7", Key::Numpad7),
+    ("Numpad8", Key::Numpad8), ("Numpad9", Key::Numpad9),
+    ("A", Key::A), ("B", Key::B), ("C", Key::C), ("D", Key::D), ("E", Key::E), ("F", Key::F), ("G", Key::G),
+    ("H", Key::H), ("I", Key::I), ("J", Key::J), ("K", Key::K), ("L", Key::L), ("M", Key::M), ("N", Key::N),
+    ("O", Key::O), ("P", Key::P), ("Q", Key::Q), ("R", Key::R), ("S", Key::S), ("T", Key::T), ("U", Key::U),
+    ("V", Key::V), ("W", Key::W), ("X", Key::X), ("Y", Key::Y), ("Z", Key::Z),
+    ("F1", Key::F1), ("F2", Key::F2), ("F3", Key::F3), ("F4", Key::F4), ("F5", Key::F5), ("F6", Key::F6),
+    ("F7", Key::F7), ("F8", Key::F8), ("F9", Key::F9), ("F10", Key::F10), ("F11", Key::F11), ("F12", Key::F12),
 ];
 
-pub fn press_osb(osb_number: u8) {
-    let key_combo = MFD_KEYS[osb_number as usize - 1];
-    for key in key_combo.iter() {
+pub fn parse_chord(chord: &str) -> Result<Vec<Key>, String> {
+    chord.split('+')
+        .map(|name| {
+            let name = name.trim();
+            KEY_NAMES.iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(name))
+                .map(|(_, key)| *key)
+                .ok_or_else(|| format!("Unknown key '{}' in '{}'", name, chord))
+        })
+        .collect()
+}
+
+#[cfg(windows)]
+pub fn available() -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(windows))]
+pub fn available() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Keyboard output is only supported on Windows - set output_mode = \"Joystick\""))
+}
+
+#[cfg(windows)]
+pub fn press_keys(keys: &[Key]) {
+    for key in keys.iter() {
         winky::press(*key);
     }
 }
 
-pub fn release_osb(osb_number: u8) {
-    let key_combo = MFD_KEYS[osb_number as usize - 1];
-    for key in key_combo.iter().rev() {
+#[cfg(windows)]
+pub fn release_keys(keys: &[Key]) {
+    for key in keys.iter().rev() {
         winky::release(*key);
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(not(windows))]
+pub fn press_keys(_keys: &[Key]) {}
+
+#[cfg(not(windows))]
+pub fn release_keys(_keys: &[Key]) {}
@@ -0,0 +1,206 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::io;
+
+use crate::config::ButtonAction;
+
+#[cfg(target_os = "linux")]
+mod rawmidi;
+#[cfg(windows)]
+mod winmm;
+
+// MIDI notes and CCs come through the same pipeline as joystick buttons, as buttons on a device of their own
+pub const MIDI_DEVICE: u32 = u32::MAX - 1;
+
+const NOTE_BUTTONS: u32 = 0x10000;
+const CC_BUTTONS: u32 = 0x20000;
+
+// A MIDI input port. Reads never block; whatever has arrived since the last read is returned.
+pub trait MidiInput: Send {
+    fn read(&mut self) -> io::Result<Vec<u8>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiMessage {
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8 },
+    ControlChange { channel: u8, controller: u8, value: u8 },
+}
+
+// A note or CC and what it does, e.g. { note = 36, action = { Osb = 1 } }
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MidiMapping {
+    #[serde(default)]
+    pub channel: Option<u8>,  // 1-16, or any channel if left out
+    #[serde(default)]
+    pub note: Option<u8>,
+    #[serde(default)]
+    pub cc: Option<u8>,
+    pub action: ButtonAction,
+}
+
+impl MidiMapping {
+    pub fn matches(&self, button_id: u32) -> bool {
+        let channel = ((button_id >> 8) & 0x0f) as u8 + 1;
+        let number = (button_id & 0xff) as u8;
+        let kind_matches = match button_id & !0xffff {
+            NOTE_BUTTONS => self.note == Some(number),
+            CC_BUTTONS => self.cc == Some(number),
+            _ => false,
+        };
+        kind_matches && self.channel.is_none_or(|c| c == channel)
+    }
+
+    // Mappings with no note or CC are filled in from the next unbound message
+    pub fn is_learning(&self) -> bool {
+        self.note.is_none() && self.cc.is_none()
+    }
+
+    pub fn learn(&mut self, button_id: u32) {
+        let number = (button_id & 0xff) as u8;
+        match button_id & !0xffff {
+            NOTE_BUTTONS => self.note = Some(number),
+            CC_BUTTONS => self.cc = Some(number),
+            _ => return,
+        }
+        self.channel = Some(((button_id >> 8) & 0x0f) as u8 + 1);
+    }
+}
+
+// Turns the raw byte stream into messages, following running status and skipping
+// anything we don't use (clock, sysex, pitch bend and so on)
+#[derive(Default)]
+pub struct MidiParser {
+    status: Option<u8>,
+    data: Vec<u8>,
+}
+
+impl MidiParser {
+    pub fn push(&mut self, byte: u8) -> Option<MidiMessage> {
+        match byte {
+            0xf8..=0xff => return None,  // Real-time bytes can appear anywhere
+            0x80..=0xef => {
+                self.status = Some(byte);
+                self.data.clear();
+                return None;
+            }
+            0xf0..=0xf7 => {
+                // System common and sysex cancel running status
+                self.status = None;
+                self.data.clear();
+                return None;
+            }
+            _ => {}
+        }
+
+        let status = self.status?;
+        self.data.push(byte);
+        if self.data.len() < message_length(status) - 1 {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data);
+
+        let channel = status & 0x0f;
+        match (status & 0xf0, data.as_slice()) {
+            (0x90, &[note, velocity]) if velocity > 0 => Some(MidiMessage::NoteOn { channel, note, velocity }),
+            (0x80 | 0x90, &[note, _]) => Some(MidiMessage::NoteOff { channel, note }),
+            (0xb0, &[controller, value]) => Some(MidiMessage::ControlChange { channel, controller, value }),
+            _ => None,
+        }
+    }
+}
+
+// Including the status byte
+pub fn message_length(status: u8) -> usize {
+    match status {
+        0xc0..=0xdf => 2,
+        0x80..=0xef => 3,
+        0xf1 | 0xf3 => 2,  // Time code quarter frame, song select
+        0xf2 => 3,         // Song position
+        _ => 1,            // Tune request, real-time bytes and anything undefined
+    }
+}
+
+// A port plus the state needed to turn its messages into button presses
+pub struct MidiSource {
+    port: Box<dyn MidiInput>,
+    parser: MidiParser,
+    controllers: HashMap<u32, bool>,  // Whether each CC is currently "pressed"
+}
+
+impl MidiSource {
+    pub fn new(port: Box<dyn MidiInput>) -> Self {
+        MidiSource { port, parser: MidiParser::default(), controllers: HashMap::new() }
+    }
+
+    // Button (id, pressed) events since the last call. Notes press on note on and release
+    // on note off; CCs press when they go past half way, so pads sending 127/0 work too.
+    pub fn poll(&mut self) -> io::Result<Vec<(u32, bool)>> {
+        let bytes = self.port.read()?;
+        let mut buttons = Vec::new();
+        for message in bytes.into_iter().filter_map(|byte| self.parser.push(byte)) {
+            match message {
+                MidiMessage::NoteOn { channel, note, .. } => buttons.push((note_button(channel, note), true)),
+                MidiMessage::NoteOff { channel, note } => buttons.push((note_button(channel, note), false)),
+                MidiMessage::ControlChange { channel, controller, value } => {
+                    let button = cc_button(channel, controller);
+                    let pressed = value >= 64;
+                    if self.controllers.insert(button, pressed).unwrap_or(false) != pressed {
+                        buttons.push((button, pressed));
+                    }
+                }
+            }
+        }
+        Ok(buttons)
+    }
+}
+
+// For showing a bound message back to the user
+pub fn describe(button_id: u32) -> String {
+    let channel = ((button_id >> 8) & 0x0f) + 1;
+    let number = button_id & 0xff;
+    match button_id & !0xffff {
+        NOTE_BUTTONS => format!("MIDI note {} (channel {})", number, channel),
+        CC_BUTTONS => format!("MIDI CC {} (channel {})", number, channel),
+        _ => format!("MIDI #{}", button_id),
+    }
+}
+
+pub fn note_button(channel: u8, note: u8) -> u32 {
+    NOTE_BUTTONS | (channel as u32) << 8 | note as u32
+}
+
+pub fn cc_button(channel: u8, controller: u8) -> u32 {
+    CC_BUTTONS | (channel as u32) << 8 | controller as u32
+}
+
+#[cfg(target_os = "linux")]
+pub fn open(device: u32) -> io::Result<MidiSource> {
+    Ok(MidiSource::new(Box::new(rawmidi::RawMidi::open(device)?)))
+}
+
+#[cfg(windows)]
+pub fn open(device: u32) -> io::Result<MidiSource> {
+    Ok(MidiSource::new(Box::new(winmm::WinMidiIn::open(device)?)))
+}
+
+// Names of the ports open() can take, by number
+#[cfg(target_os = "linux")]
+pub fn port_names() -> Vec<String> {
+    rawmidi::ports().unwrap_or_default().iter().map(|path| path.display().to_string()).collect()
+}
+
+#[cfg(windows)]
+pub fn port_names() -> Vec<String> {
+    winmm::port_names()
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn port_names() -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn open(_device: u32) -> io::Result<MidiSource> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "MIDI input is not supported on this platform"))
+}
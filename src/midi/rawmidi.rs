@@ -0,0 +1,52 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+use super::MidiInput;
+
+// ALSA exposes each hardware MIDI port as a raw byte stream at /dev/snd/midiC<card>D<device>
+pub struct RawMidi {
+    file: File,
+}
+
+// Ports are numbered in name order, so the same controller keeps its number between runs
+pub fn ports() -> io::Result<Vec<PathBuf>> {
+    let mut ports: Vec<_> = fs::read_dir("/dev/snd")?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("midi")))
+        .collect();
+    ports.sort();
+    Ok(ports)
+}
+
+impl RawMidi {
+    pub fn open(index: u32) -> io::Result<Self> {
+        let ports = ports()?;
+        let path = ports.get(index as usize).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("MIDI port {} not found ({} available)", index, ports.len()))
+        })?;
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+        Ok(RawMidi { file })
+    }
+}
+
+impl MidiInput for RawMidi {
+    fn read(&mut self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut buffer = [0u8; 256];
+        loop {
+            match self.file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => bytes.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,80 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use windows::Win32::Media::Audio::{
+    midiInClose, midiInGetDevCapsW, midiInGetNumDevs, midiInOpen, midiInStart, midiInStop, CALLBACK_FUNCTION,
+    HMIDIIN, MIDIINCAPSW,
+};
+use windows::Win32::Media::MM_MIM_DATA;
+
+use super::{message_length, MidiInput};
+
+// winmm hands us one short message at a time on its own thread; the callback packs them
+// back into bytes for the parser to pick up on the next read
+pub struct WinMidiIn {
+    handle: HMIDIIN,
+    buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+// The handle is only used from the thread that owns the WinMidiIn
+unsafe impl Send for WinMidiIn {}
+
+unsafe extern "system" fn midi_in_proc(_handle: HMIDIIN, msg: u32, instance: usize, param1: usize, _param2: usize) {
+    if msg != MM_MIM_DATA {
+        return;
+    }
+    let buffer = &*(instance as *const Mutex<Vec<u8>>);
+    let bytes = (param1 as u32).to_le_bytes();
+    if let Ok(mut buffer) = buffer.lock() {
+        buffer.extend_from_slice(&bytes[..message_length(bytes[0])]);
+    }
+}
+
+// Port names, in device number order
+pub fn port_names() -> Vec<String> {
+    (0..unsafe { midiInGetNumDevs() })
+        .map(|device| {
+            let mut caps = MIDIINCAPSW::default();
+            unsafe { midiInGetDevCapsW(device as usize, &mut caps, std::mem::size_of::<MIDIINCAPSW>() as u32) };
+            let name = caps.szPname;
+            let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+            String::from_utf16_lossy(&name[..len])
+        })
+        .collect()
+}
+
+impl WinMidiIn {
+    pub fn open(device: u32) -> io::Result<Self> {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let mut handle = HMIDIIN::default();
+        unsafe {
+            let result = midiInOpen(
+                &mut handle,
+                device,
+                midi_in_proc as *const () as usize,
+                Arc::as_ptr(&buffer) as usize,
+                CALLBACK_FUNCTION,
+            );
+            if result != 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, format!("Could not open MIDI device {} (error {})", device, result)));
+            }
+            midiInStart(handle);
+        }
+        Ok(WinMidiIn { handle, buffer })
+    }
+}
+
+impl MidiInput for WinMidiIn {
+    fn read(&mut self) -> io::Result<Vec<u8>> {
+        Ok(self.buffer.lock().map(|mut buffer| std::mem::take(&mut *buffer)).unwrap_or_default())
+    }
+}
+
+impl Drop for WinMidiIn {
+    fn drop(&mut self) {
+        // Closing waits for the callback to finish, so the buffer outlives it
+        unsafe {
+            midiInStop(self.handle);
+            midiInClose(self.handle);
+        }
+    }
+}
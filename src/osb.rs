@@ -1,31 +1,110 @@
-use crate::direction::{Direction, get_relative_directions};
+use crate::config::SelectionMode;
+use crate::direction::{Direction, get_relative_directions, get_opposite_direction};
 use crate::MfdState;
 
-pub fn calculate_osb_number(
-    mfd: MfdState,
-    side: Direction,
-    inputs: &[Direction],
-) -> Option<u8> {
-    if inputs.is_empty() {
-        return None;
+// Where a selection stands after the presses made since choosing a side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Selection {
+    Complete(u8),         // OSB chosen, as an index 0-19 around the MFD
+    Partial(Option<u8>),  // Needs more input; the index to highlight meanwhile
+    Invalid,              // Nothing can be reached from here
+}
+
+// A hat grammar for picking an OSB once the side is known. The UI asks the same
+// strategy what to highlight so it always agrees with what will be pressed.
+pub trait SelectionStrategy: Sync {
+    fn select(&self, side: Direction, inputs: &[Direction]) -> Selection;
+}
+
+// Move along the edge and confirm with the side direction
+pub struct EdgeStrategy;
+
+// Fixed code per OSB: the buttons either side of the middle are reached in
+// one press instead of two
+pub struct DirectStrategy;
+
+pub fn strategy(mode: SelectionMode) -> &'static dyn SelectionStrategy {
+    match mode {
+        SelectionMode::Edge => &EdgeStrategy,
+        SelectionMode::Direct => &DirectStrategy,
+        // Cursor mode never selects a side, so fall back to the default grammar
+        SelectionMode::Cursor => &EdgeStrategy,
     }
+}
 
-    let osb_position = calculate_side_button(side, inputs)?;
+impl SelectionStrategy for EdgeStrategy {
+    fn select(&self, side: Direction, inputs: &[Direction]) -> Selection {
+        if let Some(position) = calculate_side_button(side, inputs) {
+            return Selection::Complete(side_index(side, position));
+        }
+        if !could_lead_to_valid_osb(side, inputs) {
+            return Selection::Invalid;
+        }
 
-    // Calculate global button number
+        // Highlight the button that pressing the side direction would confirm
+        let (left_dir, right_dir) = get_relative_directions(side);
+        let position = match inputs.last() {
+            Some(d) if *d == left_dir => 1,
+            Some(d) if *d == right_dir => 3,
+            _ => 2,
+        };
+        Selection::Partial(Some(side_index(side, position)))
+    }
+}
+
+impl SelectionStrategy for DirectStrategy {
+    fn select(&self, side: Direction, inputs: &[Direction]) -> Selection {
+        if let Some(position) = calculate_direct_side_button(side, inputs) {
+            return Selection::Complete(side_index(side, position));
+        }
+        match inputs {
+            [] => Selection::Partial(Some(side_index(side, 2))),
+            // Pulled back - either corner is still possible so there's nothing to highlight
+            [b] if *b == get_opposite_direction(side) => Selection::Partial(None),
+            _ => Selection::Invalid,
+        }
+    }
+}
+
+// Cursor mode: right/left step clockwise/anticlockwise, up/down jump a whole side,
+// wrapping around the corners
+pub fn move_cursor(position: u8, direction: Direction) -> u8 {
+    let step = match direction {
+        Direction::Right => 1,
+        Direction::Left => 19,
+        Direction::Up => 5,
+        Direction::Down => 15,
+    };
+    (position % 20 + step) % 20
+}
+
+// Index of a button around the MFD, clockwise from the top left
+pub fn side_index(side: Direction, position: u8) -> u8 {
     let base_number = match side {
         Direction::Up => 0,
         Direction::Right => 5,
         Direction::Down => 10,
         Direction::Left => 15,
     };
+    base_number + position
+}
 
+pub fn side_of(index: u8) -> Direction {
+    match index {
+        0..=4 => Direction::Up,
+        5..=9 => Direction::Right,
+        10..=14 => Direction::Down,
+        _ => Direction::Left,
+    }
+}
+
+// Global OSB number as used by the key tables
+pub fn osb_number(mfd: &MfdState, index: u8) -> u8 {
     let mfd_offset = match mfd {
         MfdState::LeftMfd => 0,
         MfdState::RightMfd => 20,
     };
-
-    Some(base_number + osb_position + mfd_offset + 1)
+    index + mfd_offset + 1
 }
 
 pub fn calculate_side_button(side: Direction, inputs: &[Direction]) -> Option<u8> {
@@ -37,7 +116,7 @@ pub fn calculate_side_button(side: Direction, inputs: &[Direction]) -> Option<u8
     // The "left" and "right" directions relative to the selected side
     let (left_dir, right_dir) = get_relative_directions(side);
 
-    match (inputs.get(0), inputs.get(1)) {
+    match (inputs.first(), inputs.get(1)) {
         // Outer buttons using relative directions
         (Some(&d1), Some(&d2)) if d1 == left_dir && d2 == left_dir => Some(0),
         (Some(&d1), Some(&d2)) if d1 == left_dir && d2 == side => Some(1),
@@ -59,7 +138,7 @@ pub fn could_lead_to_valid_osb(side: Direction, inputs: &[Direction]) -> bool {
 
     let (left_dir, right_dir) = get_relative_directions(side);
 
-    match (inputs.get(0), inputs.get(1)) {
+    match (inputs.first(), inputs.get(1)) {
         // Single press that could lead to valid double press
         (Some(&d), None) if d == left_dir || d == right_dir => true,
         
@@ -69,4 +148,20 @@ pub fn could_lead_to_valid_osb(side: Direction, inputs: &[Direction]) -> bool {
         
         _ => false,
     }
-} 
\ No newline at end of file
+}
+
+pub fn calculate_direct_side_button(side: Direction, inputs: &[Direction]) -> Option<u8> {
+    let (left_dir, right_dir) = get_relative_directions(side);
+    let back_dir = get_opposite_direction(side);
+
+    match inputs {
+        // One press picks the middle or either neighbour
+        [d] if *d == side => Some(2),
+        [d] if *d == left_dir => Some(1),
+        [d] if *d == right_dir => Some(3),
+        // Pulling back first reaches the corner buttons
+        [b, d] if *b == back_dir && *d == left_dir => Some(0),
+        [b, d] if *b == back_dir && *d == right_dir => Some(4),
+        _ => None,
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::config::OutputMode;
+use crate::direction::Direction;
+use crate::joystick::{self, VirtualJoystick};
+use crate::mfd_keys::{self, Key};
+use crate::profile;
+use crate::rocker::Rocker;
+use crate::MfdState;
+
+// What pressed a chord, so letting go of it releases the right keys
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChordId {
+    Osb(u8),                        // An OSB's long press alternate
+    Rocker(MfdState, Rocker, bool), // Up or down
+    Keypad(u8),                     // Position on the keypad
+    Macro(Direction),               // Shift layer macro
+    Midi(usize),                    // Index into the MIDI mappings
+    Direct(usize),                  // Index into the direct buttons
+}
+
+struct Output {
+    mode: OutputMode,
+    joystick: Option<Box<dyn VirtualJoystick>>,
+    held: BTreeSet<u8>,                // OSBs currently pressed, so they can be released on exit
+    chords: Vec<(ChordId, Vec<Key>)>,  // Chords currently pressed
+    releases: Vec<(u8, Instant)>,      // OSBs to let go of once their pulse is over
+}
+
+static OUTPUT: Mutex<Output> = Mutex::new(Output {
+    mode: OutputMode::Keyboard,
+    joystick: None,
+    held: BTreeSet::new(),
+    chords: Vec::new(),
+    releases: Vec::new(),
+});
+
+pub fn init(mode: OutputMode) -> io::Result<()> {
+    let joystick = match mode {
+        OutputMode::Keyboard => {
+            mfd_keys::available()?;
+            None
+        }
+        OutputMode::Joystick => Some(joystick::open()?),
+    };
+    set_backend(mode, joystick);
+    Ok(())
+}
+
+pub fn set_backend(mode: OutputMode, joystick: Option<Box<dyn VirtualJoystick>>) {
+    release_all();
+    let mut output = OUTPUT.lock().unwrap();
+    output.mode = mode;
+    output.joystick = joystick;
+}
+
+pub fn press_osb(osb_number: u8) {
+    let mut output = OUTPUT.lock().unwrap();
+    output.releases.retain(|(osb, _)| *osb != osb_number);
+    output.held.insert(osb_number);
+    send(&mut output, osb_number, true);
+}
+
+pub fn release_osb(osb_number: u8) {
+    let mut output = OUTPUT.lock().unwrap();
+    output.releases.retain(|(osb, _)| *osb != osb_number);
+    output.held.remove(&osb_number);
+    send(&mut output, osb_number, false);
+}
+
+// Release later from tick(), for pulses that outlive the hat press
+pub fn release_osb_at(osb_number: u8, at: Instant) {
+    let mut output = OUTPUT.lock().unwrap();
+    output.releases.retain(|(osb, _)| *osb != osb_number);
+    output.releases.push((osb_number, at));
+}
+
+pub fn is_held(osb_number: u8) -> bool {
+    OUTPUT.lock().unwrap().held.contains(&osb_number)
+}
+
+// Chords always go out as keys, whatever the OSB output mode
+pub fn press_chord(id: ChordId, keys: Vec<Key>) {
+    mfd_keys::press_keys(&keys);
+    OUTPUT.lock().unwrap().chords.push((id, keys));
+}
+
+pub fn release_chord(id: ChordId) {
+    let mut output = OUTPUT.lock().unwrap();
+    if let Some(index) = output.chords.iter().position(|(chord, _)| *chord == id) {
+        let (_, keys) = output.chords.remove(index);
+        mfd_keys::release_keys(&keys);
+    }
+}
+
+// When tick() next has something to do
+pub fn next_release() -> Option<Instant> {
+    OUTPUT.lock().unwrap().releases.iter().map(|(_, at)| *at).min()
+}
+
+// Send any releases that have come due
+pub fn tick() {
+    let due: Vec<u8> = {
+        let output = OUTPUT.lock().unwrap();
+        let now = Instant::now();
+        output.releases.iter().filter(|(_, at)| *at <= now).map(|(osb, _)| *osb).collect()
+    };
+    for osb_number in due {
+        release_osb(osb_number);
+    }
+}
+
+// Let go of anything still held so we never leave a key stuck down in the sim
+pub fn release_all() {
+    let mut output = OUTPUT.lock().unwrap();
+    output.releases.clear();
+    let held = std::mem::take(&mut output.held);
+    for osb_number in held {
+        send(&mut output, osb_number, false);
+    }
+    for (_, keys) in std::mem::take(&mut output.chords) {
+        mfd_keys::release_keys(&keys);
+    }
+}
+
+fn send(output: &mut Output, osb_number: u8, pressed: bool) {
+    match (output.mode, output.joystick.as_mut()) {
+        (OutputMode::Joystick, Some(joystick)) => {
+            // OSB numbers map straight onto joystick buttons; there's nowhere to report a
+            // failure mid-press, and the release will try again anyway
+            let _ = joystick.set_button(osb_number, pressed);
+        }
+        _ => {
+            let keys = osb_keys(osb_number);
+            if pressed {
+                mfd_keys::press_keys(&keys);
+            } else {
+                mfd_keys::release_keys(&keys);
+            }
+        }
+    }
+}
+
+// The profile's chord for the OSB, or the BMS default if it doesn't have one
+fn osb_keys(osb_number: u8) -> Vec<Key> {
+    let chord = profile::osb_chord(osb_number).unwrap_or_else(|| mfd_keys::MFD_KEYS[osb_number as usize - 1].to_string());
+    // A chord that won't parse was reported when the config loaded
+    mfd_keys::parse_chord(&chord).unwrap_or_default()
+}
@@ -0,0 +1,207 @@
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+use crate::config::CONFIG;
+use crate::direction::Direction;
+use crate::keypad::KeypadKey;
+use crate::mfd_keys;
+use crate::rocker::Rocker;
+use crate::MfdState;
+
+const DEFAULT_PULSE: u64 = 100;
+const DEFAULT_LONG_PRESS: u64 = 600;
+
+// Per-OSB tweaks on top of the key table, stored in the [profile] section of the config
+// or in a named profile
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyProfile {
+    pub auto_repeat: Option<AutoRepeat>,  // Used by every OSB that doesn't set its own
+    pub osb: Vec<OsbSettings>,
+    pub rocker: Vec<RockerSettings>,
+    pub keypad: Vec<KeypadSettings>,
+    pub macros: Vec<MacroSettings>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OsbSettings {
+    pub osb: u8,
+    #[serde(default)]
+    pub auto_repeat: Option<AutoRepeat>,
+    #[serde(default)]
+    pub behaviour: PressBehaviour,
+    #[serde(default)]
+    pub pulse_ms: Option<u64>,       // Pulse length
+    #[serde(default)]
+    pub long_press_ms: Option<u64>,  // How long to hold before the alternate chord is sent
+    #[serde(default)]
+    pub alternate: Option<String>,   // Chord for a long press, e.g. "Ctrl+Alt+F1"
+    #[serde(default)]
+    pub chord: Option<String>,       // Sent instead of the BMS default, for other sims
+    #[serde(default)]
+    pub label: Option<String>,       // Shown on the button instead of its number, up to four characters
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum PressBehaviour {
+    #[default]
+    Hold,       // Down for as long as the hat is
+    Pulse,      // Down for pulse_ms, however long the hat is held
+    Toggle,     // One press latches, the next releases
+    LongPress,  // A tap on release, or the alternate chord once held past long_press_ms
+}
+
+// What to do with an OSB press, resolved from its settings
+#[derive(Clone, Debug, PartialEq)]
+pub enum Behaviour {
+    Hold,
+    Pulse(Duration),
+    Toggle,
+    LongPress { threshold: Duration, alternate: String },
+}
+
+// Key chords for a corner rocker, e.g. up = "Ctrl+Shift+F1"
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RockerSettings {
+    pub mfd: MfdState,
+    pub rocker: Rocker,
+    #[serde(default)]
+    pub up: Option<String>,
+    #[serde(default)]
+    pub down: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeypadSettings {
+    pub key: KeypadKey,
+    pub chord: String,
+}
+
+// A chord sent from the macro shift layer while the hat is held in this direction
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MacroSettings {
+    pub direction: Direction,
+    pub chord: String,
+}
+
+// Turns a long hold into a train of presses, for things like range steps and rockers
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AutoRepeat {
+    pub delay_ms: u64,     // How long to hold before repeating starts
+    pub interval_ms: u64,  // Time between presses once it has
+}
+
+impl KeyProfile {
+    pub fn osb_settings(&self, osb_number: u8) -> Option<&OsbSettings> {
+        self.osb.iter().find(|settings| settings.osb == osb_number)
+    }
+
+    pub fn osb_chord(&self, osb_number: u8) -> Option<&str> {
+        self.osb_settings(osb_number)?.chord.as_deref()
+    }
+
+    pub fn osb_label(&self, osb_number: u8) -> Option<&str> {
+        self.osb_settings(osb_number)?.label.as_deref()
+    }
+
+    pub fn auto_repeat(&self, osb_number: u8) -> Option<AutoRepeat> {
+        self.osb_settings(osb_number)
+            .and_then(|settings| settings.auto_repeat)
+            .or(self.auto_repeat)
+            .filter(|repeat| repeat.interval_ms > 0)
+    }
+
+    pub fn rocker_chord(&self, mfd: &MfdState, rocker: Rocker, up: bool) -> Option<&str> {
+        let settings = self.rocker.iter().find(|settings| settings.mfd == *mfd && settings.rocker == rocker)?;
+        if up { settings.up.as_deref() } else { settings.down.as_deref() }
+    }
+
+    pub fn keypad_chord(&self, key: KeypadKey) -> Option<&str> {
+        self.keypad.iter().find(|settings| settings.key == key).map(|settings| settings.chord.as_str())
+    }
+
+    pub fn macro_chord(&self, direction: Direction) -> Option<&str> {
+        self.macros.iter().find(|settings| settings.direction == direction).map(|settings| settings.chord.as_str())
+    }
+
+    // Everything that would go wrong at press time, found up front
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut check = |what: String, chord: &str| {
+            if let Err(err) = mfd_keys::parse_chord(chord) {
+                problems.push(format!("{}: {}", what, err));
+            }
+        };
+        for settings in &self.osb {
+            let what = format!("OSB {}", settings.osb);
+            for chord in [&settings.chord, &settings.alternate].into_iter().flatten() {
+                check(what.clone(), chord);
+            }
+        }
+        for settings in &self.rocker {
+            for (direction, chord) in [("up", &settings.up), ("down", &settings.down)] {
+                if let Some(chord) = chord {
+                    check(format!("{:?} {} {}", settings.mfd, settings.rocker.label(), direction), chord);
+                }
+            }
+        }
+        for settings in &self.keypad {
+            check(format!("ICP {}", settings.key.label()), &settings.chord);
+        }
+        for settings in &self.macros {
+            check(format!("Macro {:?}", settings.direction), &settings.chord);
+        }
+
+        for settings in &self.osb {
+            if !(1..=40).contains(&settings.osb) {
+                problems.push(format!("OSB {}: OSBs go from 1 to 40", settings.osb));
+            }
+            if settings.label.as_ref().is_some_and(|label| label.chars().count() > 4) {
+                problems.push(format!("OSB {}: labels only have room for four characters", settings.osb));
+            }
+        }
+        problems
+    }
+
+    pub fn behaviour(&self, osb_number: u8) -> Behaviour {
+        let Some(settings) = self.osb_settings(osb_number) else { return Behaviour::Hold };
+        match settings.behaviour {
+            PressBehaviour::Hold => Behaviour::Hold,
+            PressBehaviour::Pulse => Behaviour::Pulse(Duration::from_millis(settings.pulse_ms.unwrap_or(DEFAULT_PULSE))),
+            PressBehaviour::Toggle => Behaviour::Toggle,
+            // Without a chord there's nothing to hold for, so it's just a plain hold
+            PressBehaviour::LongPress => match &settings.alternate {
+                Some(alternate) => Behaviour::LongPress {
+                    threshold: Duration::from_millis(settings.long_press_ms.unwrap_or(DEFAULT_LONG_PRESS)),
+                    alternate: alternate.clone(),
+                },
+                None => Behaviour::Hold,
+            },
+        }
+    }
+}
+
+pub fn auto_repeat(osb_number: u8) -> Option<AutoRepeat> {
+    CONFIG.lock().ok()?.as_ref()?.key_profile().auto_repeat(osb_number)
+}
+
+pub fn behaviour(osb_number: u8) -> Behaviour {
+    CONFIG.lock().ok()
+        .and_then(|config| config.as_ref().map(|config| config.key_profile().behaviour(osb_number)))
+        .unwrap_or(Behaviour::Hold)
+}
+
+pub fn osb_chord(osb_number: u8) -> Option<String> {
+    CONFIG.lock().ok()?.as_ref()?.key_profile().osb_chord(osb_number).map(str::to_string)
+}
+
+pub fn rocker_chord(mfd: &MfdState, rocker: Rocker, up: bool) -> Option<String> {
+    CONFIG.lock().ok()?.as_ref()?.key_profile().rocker_chord(mfd, rocker, up).map(str::to_string)
+}
+
+pub fn keypad_chord(key: KeypadKey) -> Option<String> {
+    CONFIG.lock().ok()?.as_ref()?.key_profile().keypad_chord(key).map(str::to_string)
+}
+
+pub fn macro_chord(direction: Direction) -> Option<String> {
+    CONFIG.lock().ok()?.as_ref()?.key_profile().macro_chord(direction).map(str::to_string)
+}
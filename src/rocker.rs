@@ -0,0 +1,56 @@
+use serde::{Serialize, Deserialize};
+use crate::direction::Direction;
+
+// The four corner rockers on each MFD, clockwise from the top left
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Rocker {
+    Gain,
+    Sym,
+    Con,
+    Brt,
+}
+
+pub const ROCKERS: [Rocker; 4] = [Rocker::Gain, Rocker::Sym, Rocker::Con, Rocker::Brt];
+
+impl Rocker {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Rocker::Gain => "GAIN",
+            Rocker::Sym => "SYM",
+            Rocker::Con => "CON",
+            Rocker::Brt => "BRT",
+        }
+    }
+}
+
+// A corner is picked with one vertical and one horizontal press, in either order
+pub fn select_rocker(inputs: &[Direction]) -> Option<Rocker> {
+    let (vertical, horizontal) = match inputs {
+        [first @ (Direction::Up | Direction::Down), second @ (Direction::Left | Direction::Right)]
+        | [second @ (Direction::Left | Direction::Right), first @ (Direction::Up | Direction::Down)] => (*first, *second),
+        _ => return None,
+    };
+    match (vertical, horizontal) {
+        (Direction::Up, Direction::Left) => Some(Rocker::Gain),
+        (Direction::Up, _) => Some(Rocker::Sym),
+        (_, Direction::Right) => Some(Rocker::Con),
+        _ => Some(Rocker::Brt),
+    }
+}
+
+pub fn could_lead_to_rocker(inputs: &[Direction]) -> bool {
+    match inputs {
+        [] | [_] => true,
+        _ => select_rocker(inputs).is_some(),
+    }
+}
+
+pub fn could_reach(inputs: &[Direction], rocker: Rocker) -> bool {
+    match inputs {
+        [] => true,
+        [first] => [Direction::Up, Direction::Right, Direction::Down, Direction::Left]
+            .iter()
+            .any(|next| select_rocker(&[*first, *next]) == Some(rocker)),
+        _ => select_rocker(inputs) == Some(rocker),
+    }
+}
@@ -1,29 +1,209 @@
-use windows::{
-    Win32::Media::Audio::{PlaySoundA, SND_MEMORY, SND_ASYNC},
-    core::PCSTR,
-};
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(windows)]
+mod playsound;
+#[cfg(not(windows))]
+mod portable;
+pub mod voice;
+pub mod wav;
 
 // Include the click sound file directly in the binary
-const CLICK_LEFT_SOUND: &[u8] = include_bytes!("../assets/click_left.wav");
-const CLICK_RIGHT_SOUND: &[u8] = include_bytes!("../assets/click_right.wav");
+pub const CLICK_LEFT_SOUND: &[u8] = include_bytes!("../assets/click_left.wav");
+pub const CLICK_RIGHT_SOUND: &[u8] = include_bytes!("../assets/click_right.wav");
+pub const CLICK_SOUND: &[u8] = include_bytes!("../assets/click.wav");
+
+// Something worth hearing about when you can't see the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cue {
+    MfdLeft,      // A long press picked the left MFD
+    MfdRight,
+    Side,         // A side was picked and the OSB is next
+    Moved,        // A step towards an OSB or rocker, or the cursor moving
+    Osb,          // An OSB or rocker went down
+    Invalid,      // That sequence doesn't lead anywhere
+    Timeout,      // Too long between steps, so back to picking a side
+    BindingStep,  // The wizard has moved on
+}
+
+const CUES: [Cue; 8] = [Cue::MfdLeft, Cue::MfdRight, Cue::Side, Cue::Moved, Cue::Osb, Cue::Invalid, Cue::Timeout, Cue::BindingStep];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CueSettings {
+    pub enabled: bool,
+    pub volume: f32,            // 0-1
+    pub file: Option<PathBuf>,  // A WAV to play instead, relative to the config
+}
+
+impl Default for CueSettings {
+    fn default() -> Self {
+        CueSettings { enabled: true, volume: 1.0, file: None }
+    }
+}
+
+// One for each cue, as [sounds.osb] and so on in the config
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoundSettings {
+    pub mfd_left: CueSettings,
+    pub mfd_right: CueSettings,
+    pub side: CueSettings,
+    pub moved: CueSettings,
+    pub osb: CueSettings,
+    pub invalid: CueSettings,
+    pub timeout: CueSettings,
+    pub binding_step: CueSettings,
+    pub voice: VoiceSettings,
+}
+
+// Spoken MFDs, sides and OSB numbers in place of some of the cues
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoiceSettings {
+    pub pack: Option<PathBuf>,  // A directory of WAVs named for their words, relative to the config
+    pub volume: f32,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        VoiceSettings { pack: None, volume: 1.0 }
+    }
+}
+
+impl SoundSettings {
+    pub fn cue(&self, cue: Cue) -> &CueSettings {
+        match cue {
+            Cue::MfdLeft => &self.mfd_left,
+            Cue::MfdRight => &self.mfd_right,
+            Cue::Side => &self.side,
+            Cue::Moved => &self.moved,
+            Cue::Osb => &self.osb,
+            Cue::Invalid => &self.invalid,
+            Cue::Timeout => &self.timeout,
+            Cue::BindingStep => &self.binding_step,
+        }
+    }
+}
+
+// What plays when there's no file for a cue. The clicks are recorded; the rest are tones,
+// higher for progress and lower for things going wrong.
+fn built_in(cue: Cue) -> Vec<u8> {
+    match cue {
+        Cue::MfdLeft => CLICK_LEFT_SOUND.to_vec(),
+        Cue::MfdRight => CLICK_RIGHT_SOUND.to_vec(),
+        Cue::Side => CLICK_SOUND.to_vec(),
+        Cue::Moved => wav::tone(1400.0, Duration::from_millis(15)),
+        Cue::Osb => wav::tone(1000.0, Duration::from_millis(40)),
+        Cue::Invalid => wav::tone(200.0, Duration::from_millis(200)),
+        Cue::Timeout => wav::tone(400.0, Duration::from_millis(120)),
+        Cue::BindingStep => wav::tone(700.0, Duration::from_millis(60)),
+    }
+}
+
+// Somewhere for sounds to go. `wav` is a whole WAV file, and playing shouldn't wait for it to finish.
+pub trait SoundBackend: Send {
+    fn play(&mut self, cue: Cue, wav: Arc<[u8]>);
 
-pub enum ClickSound {
-    Left,
-    Right,
+    // Like play, but a word cuts off the last word if it's still being said, so quick
+    // presses don't queue up behind each other
+    fn say(&mut self, word: &str, wav: Arc<[u8]>);
 }
 
-pub fn play_click(sound: ClickSound) {
-    let sound_data = match sound {
-        ClickSound::Left => CLICK_LEFT_SOUND,
-        ClickSound::Right => CLICK_RIGHT_SOUND,
+struct Sounds {
+    backend: Option<Box<dyn SoundBackend>>,
+    cues: Vec<(Cue, Arc<[u8]>)>,           // Enabled cues only, at their volume
+    words: Vec<(&'static str, Arc<[u8]>)>,  // From the voice pack, if there is one
+}
+
+// Nothing is played until there's a backend and the cues are loaded, which keeps tests quiet
+static SOUNDS: Mutex<Sounds> = Mutex::new(Sounds { backend: None, cues: Vec::new(), words: Vec::new() });
+
+#[cfg(windows)]
+pub fn open() -> io::Result<Box<dyn SoundBackend>> {
+    Ok(Box::new(playsound::PlaySound::default()))
+}
+
+#[cfg(not(windows))]
+pub fn open() -> io::Result<Box<dyn SoundBackend>> {
+    Ok(Box::new(portable::PortableSound::open()?))
+}
+
+pub fn set_backend(backend: Option<Box<dyn SoundBackend>>) {
+    SOUNDS.lock().unwrap().backend = backend;
+}
+
+// Files are read once here rather than on every press. A file that can't be used falls back
+// to the built-in sound, and is one of the problems returned.
+pub fn load(settings: &SoundSettings, config_dir: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut cues = Vec::new();
+    for cue in CUES {
+        let cue_settings = settings.cue(cue);
+        if !cue_settings.enabled {
+            continue;
+        }
+        let file = cue_settings.file.as_ref().map(|file| config_dir.join(file));
+        let data = match file.map(|file| read_wav(&file)) {
+            Some(Ok(data)) => data,
+            Some(Err(err)) => {
+                problems.push(err);
+                built_in(cue)
+            }
+            None => built_in(cue),
+        };
+        cues.push((cue, Arc::from(wav::scale(&data, cue_settings.volume))));
+    }
+
+    let mut words = Vec::new();
+    if let Some(pack) = &settings.voice.pack {
+        match voice::load(&config_dir.join(pack)) {
+            Ok(pack) => {
+                words = pack.words.into_iter().map(|(word, data)| (word, Arc::from(wav::scale(&data, settings.voice.volume)))).collect();
+                problems.extend(pack.problems);
+            }
+            Err(err) => problems.push(err),
+        }
+    }
+
+    let mut sounds = SOUNDS.lock().unwrap();
+    sounds.cues = cues;
+    sounds.words = words;
+    problems
+}
+
+fn read_wav(path: &Path) -> Result<Vec<u8>, String> {
+    let data = fs::read(path).map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+    wav::check(&data).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(data)
+}
+
+// Says the word in place of the cue if the voice pack has it. A cue that's turned off
+// silences its words too.
+pub fn announce(word: &str, cue: Cue) {
+    let mut sounds = SOUNDS.lock().unwrap();
+    if !sounds.cues.iter().any(|(loaded, _)| *loaded == cue) {
+        return;
+    }
+    let Some((_, data)) = sounds.words.iter().find(|(loaded, _)| *loaded == word) else {
+        drop(sounds);
+        return play(cue);
     };
-    
-    // Safety: sound_data is valid for the duration of PlaySound
-    unsafe {
-        PlaySoundA(
-            PCSTR(sound_data.as_ptr()),
-            None,
-            SND_MEMORY | SND_ASYNC,
-        );
-    }
-}
\ No newline at end of file
+    let data = data.clone();
+    if let Some(backend) = sounds.backend.as_mut() {
+        backend.say(word, data);
+    }
+}
+
+pub fn play(cue: Cue) {
+    let mut sounds = SOUNDS.lock().unwrap();
+    let Some((_, data)) = sounds.cues.iter().find(|(loaded, _)| *loaded == cue) else { return };
+    let data = data.clone();
+    if let Some(backend) = sounds.backend.as_mut() {
+        backend.play(cue, data);
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+use windows::{
+    Win32::Media::Audio::{PlaySoundA, SND_MEMORY, SND_ASYNC},
+    core::PCSTR,
+};
+
+use super::{Cue, SoundBackend};
+
+// The Windows system player, straight from memory. It plays one sound at a time, each
+// cutting off the last, so only the one playing needs keeping.
+#[derive(Default)]
+pub struct PlaySound {
+    playing: Option<Arc<[u8]>>,
+}
+
+impl SoundBackend for PlaySound {
+    fn play(&mut self, _cue: Cue, wav: Arc<[u8]>) {
+        // Safety: wav is kept until the next sound replaces it
+        unsafe {
+            PlaySoundA(
+                PCSTR(wav.as_ptr()),
+                None,
+                SND_MEMORY | SND_ASYNC,
+            );
+        }
+        self.playing = Some(wav);
+    }
+
+    // Every sound cuts off the last here, words included
+    fn say(&mut self, _word: &str, wav: Arc<[u8]>) {
+        self.play(Cue::Osb, wav);
+    }
+}
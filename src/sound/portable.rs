@@ -0,0 +1,71 @@
+use rodio::{Decoder, OutputStream, Sink, Source};
+use std::io::{self, Cursor};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use super::{Cue, SoundBackend};
+
+enum Sound {
+    Cue(Arc<[u8]>),
+    Word(Arc<[u8]>),
+}
+
+// The default output device through rodio. The stream can't leave the thread that opened it,
+// so sounds are handed to a thread that keeps it.
+pub struct PortableSound {
+    sounds: Sender<Sound>,
+}
+
+impl PortableSound {
+    pub fn open() -> io::Result<Self> {
+        let (sounds, queue) = mpsc::channel::<Sound>();
+        let (opened, result) = mpsc::channel();
+        thread::spawn(move || {
+            let (_stream, handle) = match OutputStream::try_default() {
+                Ok(output) => output,
+                Err(err) => {
+                    let _ = opened.send(Err(err.to_string()));
+                    return;
+                }
+            };
+            let _ = opened.send(Ok(()));
+            // Cues mix with each other and with words; words get a sink of their own so the
+            // last one can be stopped
+            let mut saying: Option<Sink> = None;
+            for sound in queue {
+                match sound {
+                    Sound::Cue(wav) => {
+                        if let Ok(source) = Decoder::new(Cursor::new(wav)) {
+                            let _ = handle.play_raw(source.convert_samples());
+                        }
+                    }
+                    Sound::Word(wav) => {
+                        if let Some(sink) = saying.take() {
+                            sink.stop();
+                        }
+                        if let (Ok(sink), Ok(source)) = (Sink::try_new(&handle), Decoder::new(Cursor::new(wav))) {
+                            sink.append(source);
+                            saying = Some(sink);
+                        }
+                    }
+                }
+            }
+        });
+        match result.recv() {
+            Ok(Ok(())) => Ok(PortableSound { sounds }),
+            Ok(Err(err)) => Err(io::Error::new(io::ErrorKind::NotFound, format!("No sound output: {}", err))),
+            Err(_) => Err(io::Error::other("The sound thread stopped")),
+        }
+    }
+}
+
+impl SoundBackend for PortableSound {
+    fn play(&mut self, _cue: Cue, wav: Arc<[u8]>) {
+        let _ = self.sounds.send(Sound::Cue(wav));
+    }
+
+    fn say(&mut self, _word: &str, wav: Arc<[u8]>) {
+        let _ = self.sounds.send(Sound::Word(wav));
+    }
+}
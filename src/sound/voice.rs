@@ -0,0 +1,53 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::direction::Direction;
+
+// Every word a voice pack can have, each in a file of its own: left.wav, top.wav, fourteen.wav
+pub const WORDS: [&str; 24] = [
+    "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen", "nineteen", "twenty",
+    "left", "right", "top", "bottom",
+];
+
+// OSBs are said by their number on their own MFD, 1-20
+pub fn osb_word(osb_number: u8) -> &'static str {
+    WORDS[(osb_number as usize - 1) % 20]
+}
+
+pub fn side_word(side: Direction) -> &'static str {
+    match side {
+        Direction::Up => "top",
+        Direction::Right => "right",
+        Direction::Down => "bottom",
+        Direction::Left => "left",
+    }
+}
+
+// The words a pack has, as WAV files, plus the problems with any it has that can't be used.
+// A pack doesn't need every word; the ones it leaves out get the usual cue.
+pub struct Pack {
+    pub words: Vec<(&'static str, Vec<u8>)>,
+    pub problems: Vec<String>,
+}
+
+pub fn load(dir: &Path) -> Result<Pack, String> {
+    if !dir.is_dir() {
+        return Err(format!("There's no voice pack at {}", dir.display()));
+    }
+    let mut words = Vec::new();
+    let mut problems = Vec::new();
+    for word in WORDS {
+        let path = dir.join(format!("{}.wav", word));
+        match fs::read(&path) {
+            Ok(data) => match super::wav::check(&data) {
+                Ok(()) => words.push((word, data)),
+                Err(err) => problems.push(format!("{}: {}", path.display(), err)),
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => problems.push(format!("Couldn't read {}: {}", path.display(), err)),
+        }
+    }
+    Ok(Pack { words, problems })
+}
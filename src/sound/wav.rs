@@ -0,0 +1,93 @@
+use std::f32::consts::TAU;
+use std::ops::Range;
+use std::time::Duration;
+
+const TONE_RATE: u32 = 44100;
+
+// Where the samples are in a PCM WAV file, and how many bits each one has
+fn samples(wav: &[u8]) -> Result<(Range<usize>, u16), String> {
+    if wav.len() < 12 || &wav[..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return Err("Not a WAV file".to_string());
+    }
+    let mut bits = None;
+    let mut at = 12;
+    while at + 8 <= wav.len() {
+        let id = &wav[at..at + 4];
+        let len = u32::from_le_bytes(wav[at + 4..at + 8].try_into().unwrap()) as usize;
+        let body = at + 8..(at + 8 + len).min(wav.len());
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let format = u16::from_le_bytes([wav[body.start], wav[body.start + 1]]);
+                if format != 1 {
+                    return Err("Only uncompressed (PCM) WAV files can be played".to_string());
+                }
+                bits = Some(u16::from_le_bytes([wav[body.start + 14], wav[body.start + 15]]));
+            }
+            b"data" => {
+                let bits = bits.ok_or("The WAV file has no format")?;
+                return Ok((body, bits));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        at = body.end + len % 2;
+    }
+    Err("The WAV file has no sound in it".to_string())
+}
+
+pub fn check(wav: &[u8]) -> Result<(), String> {
+    samples(wav).map(|_| ())
+}
+
+// The same file, quieter. Volume is done here rather than by the backends, as not all of them can.
+pub fn scale(wav: &[u8], volume: f32) -> Vec<u8> {
+    let volume = volume.clamp(0.0, 1.0);
+    let mut scaled = wav.to_vec();
+    let Ok((range, bits)) = samples(wav) else { return scaled };
+    if volume == 1.0 {
+        return scaled;
+    }
+    match bits {
+        8 => {
+            // Unsigned, centred on 128
+            for sample in &mut scaled[range] {
+                *sample = (128.0 + (*sample as f32 - 128.0) * volume) as u8;
+            }
+        }
+        16 => {
+            for sample in scaled[range].chunks_exact_mut(2) {
+                let value = (i16::from_le_bytes([sample[0], sample[1]]) as f32 * volume) as i16;
+                sample.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+        _ => {}
+    }
+    scaled
+}
+
+// A short sine wave as a mono 16-bit WAV file
+pub fn tone(frequency: f32, length: Duration) -> Vec<u8> {
+    let count = (TONE_RATE as f32 * length.as_secs_f32()) as u32;
+    let mut wav = Vec::with_capacity(44 + count as usize * 2);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + count * 2).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());  // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes());  // Mono
+    wav.extend_from_slice(&TONE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(TONE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(count * 2).to_le_bytes());
+    // 5ms fades at each end so it doesn't pop
+    let fade = TONE_RATE as f32 * 0.005;
+    for index in 0..count {
+        let envelope = (index.min(count - index) as f32 / fade).min(1.0);
+        let phase = index as f32 / TONE_RATE as f32 * frequency * TAU;
+        let sample = (phase.sin() * envelope * 0.5 * i16::MAX as f32) as i16;
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
@@ -1,16 +1,53 @@
 use super::*;
 use crate::config::Config;
 use crate::config::ButtonBindings;
+use crate::osb::SelectionStrategy;
 
-fn setup_test_config() {
-    let mut config = Config::default();
-    config.button_bindings = ButtonBindings {
-        up: (1, 1),
-        right: (1, 2),
-        down: (1, 3),
-        left: (1, 4),
+// Tests share the global CONFIG, so each one holds this lock while it runs
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+fn setup_test_config() -> std::sync::MutexGuard<'static, ()> {
+    setup_test_config_with(|_| {})
+}
+
+fn setup_test_config_with(customise: impl FnOnce(&mut Config)) -> std::sync::MutexGuard<'static, ()> {
+    let lock = TEST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    // Keep saves out of the real config
+    config::set_config_path(std::env::temp_dir().join("superhat-test").join("superhat.cfg"));
+    let mut config = Config {
+        button_bindings: ButtonBindings {
+            up: (1, 1),
+            right: (1, 2),
+            down: (1, 3),
+            left: (1, 4),
+            confirm: None,
+            modifier: None,
+        },
+        ..Default::default()
     };
+    customise(&mut config);
     *CONFIG.lock().unwrap() = Some(config);
+    lock
+}
+
+fn test_inputs() -> Inputs {
+    Inputs {
+        button_press_times: HashMap::new(),
+        long_press_detected: false,
+        hats: Vec::new(),
+        device_names: HashMap::new(),
+    }
+}
+
+// Pick a button in the binding wizard and confirm it, leaving a human gap between the presses
+fn bind_button(device_id: u32, button_id: u32, app_state: &mut AppState, inputs: &mut Inputs, ui: &mut Ui) {
+    process_button(true, device_id, button_id, app_state, inputs, ui);
+    process_button(false, device_id, button_id, app_state, inputs, ui);
+    if let AppState::Binding(wizard) = app_state {
+        wizard.detected_at -= Duration::from_secs(1);
+    }
+    process_button(true, device_id, button_id, app_state, inputs, ui);
+    process_button(false, device_id, button_id, app_state, inputs, ui);
 }
 
 fn simulate_button_event(
@@ -31,7 +68,7 @@ fn simulate_button_event(
 
 #[test]
 fn test_long_press_mfd_selection() {
-    setup_test_config();
+    let _lock = setup_test_config();
     let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
     
     // Simulate long press of right button
@@ -48,7 +85,7 @@ fn test_long_press_mfd_selection() {
 
 #[test]
 fn test_long_press_during_osb_selection() {
-    setup_test_config();
+    let _lock = setup_test_config();
     let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
     
     // Start OSB selection with short press
@@ -68,14 +105,14 @@ fn test_long_press_during_osb_selection() {
 
 #[test]
 fn test_timeout_during_long_press() {
-    setup_test_config();
+    let _lock = setup_test_config();
     let mut app_state = AppState::SelectingOSB {
         mfd: MfdState::LeftMfd,
         side: Direction::Up,
         inputs: vec![],
         last_input_time: Instant::now() - TIMEOUT_DURATION - Duration::from_millis(100),
     };
-    let mut ui = Ui::new().unwrap();
+    let mut ui = Ui::headless();
     
     // Check timeout
     check_for_timeouts(&mut app_state, &mut ui).unwrap();
@@ -86,7 +123,7 @@ fn test_timeout_during_long_press() {
 
 #[test]
 fn test_short_press_after_long_press() {
-    setup_test_config();
+    let _lock = setup_test_config();
     let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
     
     // Long press right to select right MFD
@@ -108,7 +145,7 @@ fn test_short_press_after_long_press() {
 
 #[test]
 fn test_osb_selection_sequence() {
-    setup_test_config();
+    let _lock = setup_test_config();
     let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
     
     // Select top row, middle OSB (OSB 3)
@@ -126,7 +163,8 @@ fn test_osb_selection_sequence() {
     
     assert!(matches!(app_state, AppState::OSBPressed { 
         mfd: MfdState::LeftMfd,
-        osb_number: 3
+        osb_number: 3,
+        ..
     }));
     
     // Release button
@@ -139,7 +177,7 @@ fn test_osb_selection_sequence() {
 
 #[test]
 fn test_complex_osb_sequence() {
-    setup_test_config();
+    let _lock = setup_test_config();
     let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
     
     // Select OSB 10 on left MFD
@@ -153,7 +191,8 @@ fn test_complex_osb_sequence() {
     
     assert!(matches!(app_state, AppState::OSBPressed { 
         mfd: MfdState::LeftMfd,
-        osb_number: 10
+        osb_number: 10,
+        ..
     }));
     
     simulate_button_event(InputEventType::ButtonUp, Direction::Down, &mut app_state, false);
@@ -161,7 +200,7 @@ fn test_complex_osb_sequence() {
 
 #[test]
 fn test_complex_mfd_switching_sequence() {
-    setup_test_config();
+    let _lock = setup_test_config();
     let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
     
     // Long press to switch to right MFD
@@ -181,7 +220,8 @@ fn test_complex_mfd_switching_sequence() {
     
     assert!(matches!(app_state, AppState::OSBPressed { 
         mfd: MfdState::RightMfd,
-        osb_number: 23  // OSB 3 + 20 for right MFD
+        osb_number: 23,  // OSB 3 + 20 for right MFD
+        ..
     }));
     
     // Release OSB
@@ -199,7 +239,7 @@ fn test_complex_mfd_switching_sequence() {
 
 #[test]
 fn test_mixed_long_press_and_osb_sequence() {
-    setup_test_config();
+    let _lock = setup_test_config();
     let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
     
     // Press OSB on left MFD (middle left OSB - OSB 18)
@@ -210,7 +250,8 @@ fn test_mixed_long_press_and_osb_sequence() {
     
     assert!(matches!(app_state, AppState::OSBPressed { 
         mfd: MfdState::LeftMfd,
-        osb_number: 18  // Middle left OSB
+        osb_number: 18,  // Middle left OSB
+        ..
     }));
     
     // Release button
@@ -236,7 +277,7 @@ fn test_mixed_long_press_and_osb_sequence() {
 
 #[test]
 fn test_osb_numbering() {
-    setup_test_config();
+    let _lock = setup_test_config();
     let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
     
     // Test each side's middle button
@@ -257,7 +298,8 @@ fn test_osb_numbering() {
         
         assert!(matches!(app_state, AppState::OSBPressed { 
             mfd: MfdState::LeftMfd,
-            osb_number: n
+            osb_number: n,
+            ..
         } if n == expected_osb));
         
         // Release and reset
@@ -267,7 +309,7 @@ fn test_osb_numbering() {
 
 #[test]
 fn test_long_press_detection() {
-    setup_test_config();
+    let _lock = setup_test_config();
     let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
     let mut button_press_times = HashMap::new();
     let mut long_press_detected = false;
@@ -286,11 +328,9 @@ fn test_long_press_detection() {
     
     // Check press times - should not trigger long press yet
     for (&(dev, btn), &press_time) in button_press_times.iter() {
-        if !long_press_detected && press_time.elapsed() >= LONGPRESS_DURATION {
-            if let Some(_) = map_button_to_direction(dev, btn) {
-                long_press_detected = true;
-                handle_input_event(InputEventType::LongPress, btn, dev, &mut app_state, true);
-            }
+        if !long_press_detected && press_time.elapsed() >= LONGPRESS_DURATION && map_button_to_direction(dev, btn).is_some() {
+            long_press_detected = true;
+            handle_input_event(InputEventType::LongPress, btn, dev, &mut app_state, true);
         }
     }
     assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
@@ -301,15 +341,1611 @@ fn test_long_press_detection() {
     
     // Check press times again - should trigger long press now
     for (&(dev, btn), &press_time) in button_press_times.iter() {
-        if !long_press_detected && press_time.elapsed() >= LONGPRESS_DURATION {
-            if let Some(_) = map_button_to_direction(dev, btn) {
-                long_press_detected = true;
-                handle_input_event(InputEventType::LongPress, btn, dev, &mut app_state, true);
-            }
+        if !long_press_detected && press_time.elapsed() >= LONGPRESS_DURATION && map_button_to_direction(dev, btn).is_some() {
+            long_press_detected = true;
+            handle_input_event(InputEventType::LongPress, btn, dev, &mut app_state, true);
         }
     }
     
     // Verify long press was detected and state changed to right MFD
     assert!(long_press_detected);
     assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
-}
\ No newline at end of file
+}
+
+struct RecordingJoystick(std::sync::Arc<Mutex<Vec<(u8, bool)>>>);
+
+impl joystick::VirtualJoystick for RecordingJoystick {
+    fn set_button(&mut self, button: u8, pressed: bool) -> io::Result<()> {
+        self.0.lock().unwrap().push((button, pressed));
+        Ok(())
+    }
+}
+
+// Puts the keyboard output back when a test that swapped it out is done, even if it failed
+struct RestoreKeyboardOutput;
+
+impl Drop for RestoreKeyboardOutput {
+    fn drop(&mut self) {
+        output::set_backend(config::OutputMode::Keyboard, None);
+    }
+}
+
+#[test]
+fn test_joystick_output_maps_osb_to_button() {
+    let _lock = setup_test_config();
+    let events = std::sync::Arc::new(Mutex::new(Vec::new()));
+    output::set_backend(config::OutputMode::Joystick, Some(Box::new(RecordingJoystick(events.clone()))));
+    let _restore = RestoreKeyboardOutput;
+
+    // OSB 30 on the right MFD (right side, bottom)
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::RightMfd };
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Down, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Down, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Down, &mut app_state, false);
+
+    // Other tests share the output backend, so only look for our own button
+    let events = events.lock().unwrap();
+    let ours: Vec<_> = events.iter().filter(|(button, _)| *button == 30).collect();
+    assert_eq!(ours, vec![&(30, true), &(30, false)]);
+}
+
+const ALL_DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Right, Direction::Down, Direction::Left];
+
+// Every hat sequence of up to `max_len` presses, shortest first
+fn all_sequences(max_len: usize) -> Vec<Vec<Direction>> {
+    let mut sequences = vec![vec![]];
+    let mut frontier = vec![vec![]];
+    for _ in 0..max_len {
+        frontier = frontier.iter().flat_map(|seq: &Vec<Direction>| {
+            ALL_DIRECTIONS.iter().map(move |d| {
+                let mut next = seq.clone();
+                next.push(*d);
+                next
+            })
+        }).collect();
+        sequences.extend(frontier.clone());
+    }
+    sequences
+}
+
+fn select_osb(strategy: &dyn osb::SelectionStrategy, mfd: MfdState, side: Direction, inputs: &[Direction]) -> Option<u8> {
+    match strategy.select(side, inputs) {
+        osb::Selection::Complete(index) => Some(osb::osb_number(&mfd, index)),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_direct_entry_codes() {
+    let direct = &osb::DirectStrategy;
+
+    // Top side: neighbours in one press, corners by pulling back first
+    let cases = [
+        (vec![Direction::Up], 3),
+        (vec![Direction::Left], 2),
+        (vec![Direction::Right], 4),
+        (vec![Direction::Down, Direction::Left], 1),
+        (vec![Direction::Down, Direction::Right], 5),
+    ];
+    for (inputs, expected) in cases {
+        assert_eq!(select_osb(direct, MfdState::LeftMfd, Direction::Up, &inputs), Some(expected));
+    }
+
+    // Left side is mirrored into the bottom-to-top numbering
+    assert_eq!(select_osb(direct, MfdState::RightMfd, Direction::Left, &[Direction::Down]), Some(37));
+    assert_eq!(select_osb(direct, MfdState::RightMfd, Direction::Left, &[Direction::Right, Direction::Up]), Some(40));
+}
+
+#[test]
+fn test_selection_strategies_exhaustive() {
+    let strategies: [&dyn osb::SelectionStrategy; 2] = [&osb::EdgeStrategy, &osb::DirectStrategy];
+
+    for strategy in strategies {
+        let mut reached = Vec::new();
+
+        for side in ALL_DIRECTIONS {
+            let side_range = osb::side_index(side, 0)..osb::side_index(side, 5);
+
+            for inputs in all_sequences(3) {
+                // Only sequences a user can actually enter - every shorter prefix was still partial
+                let reachable = (0..inputs.len()).all(|len| {
+                    matches!(strategy.select(side, &inputs[..len]), osb::Selection::Partial(_))
+                });
+                if !reachable {
+                    continue;
+                }
+
+                match strategy.select(side, &inputs) {
+                    osb::Selection::Complete(index) => {
+                        assert!(side_range.contains(&index), "{:?} {:?} left the side", side, inputs);
+                        reached.push(index);
+                    }
+                    osb::Selection::Partial(highlight) => {
+                        // Everything resolves within two presses after the side
+                        assert!(inputs.len() < 2, "{:?} {:?} still partial", side, inputs);
+                        if let Some(index) = highlight {
+                            assert!(side_range.contains(&index));
+                        }
+                    }
+                    osb::Selection::Invalid => {}
+                }
+            }
+        }
+
+        // Every OSB on the MFD has exactly one code
+        reached.sort();
+        assert_eq!(reached, (0..20).collect::<Vec<u8>>());
+    }
+}
+
+#[test]
+fn test_edge_strategy_highlight() {
+    let edge = &osb::EdgeStrategy;
+
+    // Right side: starts on the middle, moves with the relative directions
+    assert_eq!(edge.select(Direction::Right, &[]), osb::Selection::Partial(Some(7)));
+    assert_eq!(edge.select(Direction::Right, &[Direction::Up]), osb::Selection::Partial(Some(6)));
+    assert_eq!(edge.select(Direction::Right, &[Direction::Down]), osb::Selection::Partial(Some(8)));
+    assert_eq!(edge.select(Direction::Right, &[Direction::Left]), osb::Selection::Invalid);
+    assert_eq!(edge.select(Direction::Right, &[Direction::Up, Direction::Up]), osb::Selection::Complete(5));
+}
+
+#[test]
+fn test_cursor_wraps_around_bezel() {
+    // Steps follow BUTTON_POSITIONS order, which runs clockwise
+    assert_eq!(osb::move_cursor(0, Direction::Left), 19);
+    assert_eq!(osb::move_cursor(19, Direction::Right), 0);
+    assert_eq!(osb::move_cursor(4, Direction::Right), 5);
+    assert_eq!(osb::move_cursor(14, Direction::Right), 15);
+
+    // Up/down jump a whole side
+    assert_eq!(osb::move_cursor(2, Direction::Up), 7);
+    assert_eq!(osb::move_cursor(17, Direction::Up), 2);
+    assert_eq!(osb::move_cursor(2, Direction::Down), 17);
+}
+
+#[test]
+fn test_cursor_position_out_of_range() {
+    // A hand-edited position past the bezel is reported, and never reaches the key tables
+    let config_str = format!("version = {}\ncursor_positions = [50, 0]\n", config::CONFIG_VERSION);
+    let config = config::parse_config(&config_str).unwrap();
+    assert_eq!(config.problems(), vec!["Left cursor position: must be below 20".to_string()]);
+    assert!(config::check_reload(config.clone()).is_err());
+
+    let _lock = setup_test_config_with(|test_config| test_config.cursor_positions = config.cursor_positions);
+    assert_eq!(config::cursor_position(&MfdState::LeftMfd), 10);
+    assert_eq!(osb::move_cursor(255, Direction::Down), 10);
+}
+
+#[test]
+fn test_cursor_mode_long_press_confirm() {
+    let _lock = setup_test_config_with(|config| {
+        config.selection_mode = config::SelectionMode::Cursor;
+        config.cursor_positions = [0, 7];
+    });
+    let mut app_state = idle_state(MfdState::LeftMfd);
+    assert!(matches!(app_state, AppState::CursorRoaming { mfd: MfdState::LeftMfd, position: 0 }));
+
+    // Short presses move the cursor on release
+    for _ in 0..2 {
+        simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+        simulate_button_event(InputEventType::ButtonUp, Direction::Right, &mut app_state, false);
+    }
+    assert!(matches!(app_state, AppState::CursorRoaming { position: 2, .. }));
+
+    // Long press up presses the OSB under the cursor until released
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+    simulate_button_event(InputEventType::LongPress, Direction::Up, &mut app_state, true);
+    assert!(matches!(app_state, AppState::OSBPressed { mfd: MfdState::LeftMfd, osb_number: 3, .. }));
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, &mut app_state, true);
+    assert!(matches!(app_state, AppState::CursorRoaming { mfd: MfdState::LeftMfd, position: 2 }));
+
+    // Switching MFD restores that MFD's cursor, and switching back restores ours
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::LongPress, Direction::Right, &mut app_state, true);
+    assert!(matches!(app_state, AppState::CursorRoaming { mfd: MfdState::RightMfd, position: 7 }));
+    simulate_button_event(InputEventType::ButtonDown, Direction::Left, &mut app_state, false);
+    simulate_button_event(InputEventType::LongPress, Direction::Left, &mut app_state, true);
+    assert!(matches!(app_state, AppState::CursorRoaming { mfd: MfdState::LeftMfd, position: 2 }));
+}
+
+#[test]
+fn test_cursor_mode_confirm_button() {
+    let _lock = setup_test_config_with(|config| {
+        config.selection_mode = config::SelectionMode::Cursor;
+        config.button_bindings.confirm = Some((1, 5));
+        config.cursor_positions = [12, 0];
+    });
+    let mut app_state = idle_state(MfdState::LeftMfd);
+
+    assert!(is_confirm_button(1, 5));
+    handle_confirm(true, &mut app_state);
+    assert!(matches!(app_state, AppState::OSBPressed { mfd: MfdState::LeftMfd, osb_number: 13, .. }));
+    handle_confirm(false, &mut app_state);
+    assert!(matches!(app_state, AppState::CursorRoaming { mfd: MfdState::LeftMfd, position: 12 }));
+}
+
+#[test]
+fn test_history_most_recent_first() {
+    let _lock = setup_test_config();
+
+    for osb in [21, 22, 23, 22] {
+        history::record(&MfdState::RightMfd, osb);
+    }
+    let recent = history::recent(&MfdState::RightMfd);
+    assert_eq!(&recent[..3], &[22, 23, 21]);
+    assert_eq!(history::last(&MfdState::RightMfd), Some(22));
+
+    for osb in 24..=30 {
+        history::record(&MfdState::RightMfd, osb);
+    }
+    assert_eq!(history::recent(&MfdState::RightMfd), vec![30, 29, 28, 27, 26]);
+}
+
+#[test]
+fn test_double_tap_repeats_last_osb() {
+    let _lock = setup_test_config_with(|config| config.repeat_gesture = config::RepeatGesture::DoubleTap);
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+
+    // OSB 7 - right side, one up from the middle
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Right, &mut app_state, false);
+    assert!(matches!(app_state, AppState::WaitingForSide { .. }));
+
+    // Quick double tap on the right presses it again
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    assert!(matches!(app_state, AppState::OSBPressed { mfd: MfdState::LeftMfd, osb_number: 7, .. }));
+    simulate_button_event(InputEventType::ButtonUp, Direction::Right, &mut app_state, false);
+
+    // A slower second press is still the middle OSB
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Right, &mut app_state, false);
+    if let AppState::SelectingOSB { last_input_time, .. } = &mut app_state {
+        *last_input_time -= DOUBLE_TAP_DURATION;
+    }
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    assert!(matches!(app_state, AppState::OSBPressed { mfd: MfdState::LeftMfd, osb_number: 8, .. }));
+    simulate_button_event(InputEventType::ButtonUp, Direction::Right, &mut app_state, false);
+
+    // Double tapping a different side is just its middle OSB
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+    assert!(matches!(app_state, AppState::OSBPressed { mfd: MfdState::LeftMfd, osb_number: 3, .. }));
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, &mut app_state, false);
+}
+
+#[test]
+fn test_double_tap_off_by_default() {
+    let _lock = setup_test_config();
+    history::record(&MfdState::LeftMfd, 14);
+
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    simulate_button_event(InputEventType::ButtonDown, Direction::Down, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Down, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Down, &mut app_state, false);
+    assert!(matches!(app_state, AppState::OSBPressed { mfd: MfdState::LeftMfd, osb_number: 13, .. }));
+    simulate_button_event(InputEventType::ButtonUp, Direction::Down, &mut app_state, false);
+}
+
+fn age_press(app_state: &mut AppState, by: Duration) {
+    if let AppState::OSBPressed { pressed_at, .. } = app_state {
+        *pressed_at = Instant::now() - by;
+    }
+}
+
+#[test]
+fn test_auto_repeat_pulses_while_held() {
+    let _lock = setup_test_config_with(|config| {
+        config.profile.osb.push(profile::OsbSettings {
+            osb: 3,
+            auto_repeat: Some(profile::AutoRepeat { delay_ms: 400, interval_ms: 100 }),
+            ..Default::default()
+        });
+    });
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+
+    // Nothing before the initial delay
+    assert!(!check_held_osb(&mut app_state));
+    assert!(matches!(app_state, AppState::OSBPressed { osb_number: 3, repeats: 0, .. }));
+
+    // Then up for half the interval, down for the other half
+    age_press(&mut app_state, Duration::from_millis(410));
+    assert!(check_held_osb(&mut app_state));
+    assert!(matches!(app_state, AppState::OSBPressed { repeats: 1, .. }));
+    // The main loop wakes up in time for the next half, 40ms away
+    let sleep = sleep_duration(&app_state, &test_inputs(), Instant::now());
+    assert!(sleep <= Duration::from_millis(40) && sleep > Duration::from_millis(30), "{:?}", sleep);
+    age_press(&mut app_state, Duration::from_millis(460));
+    assert!(check_held_osb(&mut app_state));
+    assert!(matches!(app_state, AppState::OSBPressed { repeats: 2, .. }));
+    assert!(!check_held_osb(&mut app_state));
+
+    // A stalled loop skips ahead instead of sending a burst
+    age_press(&mut app_state, Duration::from_millis(1000));
+    assert!(check_held_osb(&mut app_state));
+    assert!(matches!(app_state, AppState::OSBPressed { repeats: 13, .. }));
+
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, &mut app_state, false);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+    // With nothing due it polls as slowly as it always did
+    output::release_all();
+    assert_eq!(sleep_duration(&app_state, &test_inputs(), Instant::now()), MAX_SLEEP);
+}
+
+#[test]
+fn test_auto_repeat_settings() {
+    let mut key_profile = profile::KeyProfile::default();
+    assert_eq!(key_profile.auto_repeat(5), None);
+
+    // Profile-wide default, overridden per OSB
+    let slow = profile::AutoRepeat { delay_ms: 500, interval_ms: 250 };
+    let fast = profile::AutoRepeat { delay_ms: 200, interval_ms: 50 };
+    key_profile.auto_repeat = Some(slow);
+    key_profile.osb.push(profile::OsbSettings { osb: 5, auto_repeat: Some(fast), ..Default::default() });
+    assert_eq!(key_profile.auto_repeat(5), Some(fast));
+    assert_eq!(key_profile.auto_repeat(6), Some(slow));
+
+    // A zero interval turns it off for that OSB
+    key_profile.osb.push(profile::OsbSettings { osb: 7, auto_repeat: Some(profile::AutoRepeat { delay_ms: 0, interval_ms: 0 }), ..Default::default() });
+    assert_eq!(key_profile.auto_repeat(7), None);
+}
+
+fn with_behaviour(config: &mut Config, osb: u8, behaviour: profile::PressBehaviour) -> &mut profile::OsbSettings {
+    config.profile.osb.push(profile::OsbSettings { osb, behaviour, ..Default::default() });
+    config.profile.osb.last_mut().unwrap()
+}
+
+// Up, up, up: OSB 3 on the left MFD, left held down
+fn hold_osb_3(app_state: &mut AppState) {
+    *app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, app_state, false);
+}
+
+fn release_hat(app_state: &mut AppState) {
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, app_state, false);
+}
+
+#[test]
+fn test_pulse_behaviour() {
+    let _lock = setup_test_config_with(|config| {
+        with_behaviour(config, 3, profile::PressBehaviour::Pulse).pulse_ms = Some(20);
+    });
+    output::release_all();
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+
+    // Held past the pulse: released while the hat is still down
+    hold_osb_3(&mut app_state);
+    assert!(output::is_held(3));
+    age_press(&mut app_state, Duration::from_millis(30));
+    assert!(check_held_osb(&mut app_state));
+    assert!(!output::is_held(3));
+    release_hat(&mut app_state);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+
+    // Let go early: the pulse still runs its full length
+    hold_osb_3(&mut app_state);
+    release_hat(&mut app_state);
+    assert!(output::is_held(3));
+    std::thread::sleep(Duration::from_millis(30));
+    output::tick();
+    assert!(!output::is_held(3));
+}
+
+#[test]
+fn test_toggle_behaviour() {
+    let _lock = setup_test_config_with(|config| {
+        with_behaviour(config, 3, profile::PressBehaviour::Toggle);
+    });
+    output::release_all();
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+
+    // Latches on the first press and stays down after the hat is released
+    hold_osb_3(&mut app_state);
+    release_hat(&mut app_state);
+    assert!(output::is_held(3));
+
+    hold_osb_3(&mut app_state);
+    assert!(!output::is_held(3));
+    release_hat(&mut app_state);
+    assert!(!output::is_held(3));
+}
+
+#[test]
+fn test_long_press_alternate_behaviour() {
+    let _lock = setup_test_config_with(|config| {
+        let settings = with_behaviour(config, 3, profile::PressBehaviour::LongPress);
+        settings.long_press_ms = Some(300);
+        settings.alternate = Some("Ctrl+Shift+F5".to_string());
+    });
+    output::release_all();
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+
+    // A short press taps the OSB on release
+    hold_osb_3(&mut app_state);
+    assert!(!output::is_held(3));
+    assert!(!check_held_osb(&mut app_state));
+    release_hat(&mut app_state);
+    assert!(output::is_held(3));
+    std::thread::sleep(TAP_DURATION + Duration::from_millis(10));
+    output::tick();
+    assert!(!output::is_held(3));
+
+    // A long one sends the chord instead, and never the OSB
+    hold_osb_3(&mut app_state);
+    age_press(&mut app_state, Duration::from_millis(310));
+    assert!(check_held_osb(&mut app_state));
+    assert!(matches!(app_state, AppState::OSBPressed { osb_number: 3, alternate: true, .. }));
+    assert!(!check_held_osb(&mut app_state));
+    release_hat(&mut app_state);
+    assert!(!output::is_held(3));
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+}
+
+#[test]
+fn test_behaviour_settings() {
+    let mut key_profile = profile::KeyProfile::default();
+    assert_eq!(key_profile.behaviour(3), profile::Behaviour::Hold);
+
+    key_profile.osb.push(profile::OsbSettings { osb: 3, behaviour: profile::PressBehaviour::Pulse, ..Default::default() });
+    assert_eq!(key_profile.behaviour(3), profile::Behaviour::Pulse(Duration::from_millis(100)));
+
+    // A long press with no chord to send is just a hold
+    key_profile.osb.push(profile::OsbSettings { osb: 4, behaviour: profile::PressBehaviour::LongPress, ..Default::default() });
+    assert_eq!(key_profile.behaviour(4), profile::Behaviour::Hold);
+
+    assert!(mfd_keys::parse_chord("ctrl+ALT+f1").is_ok());
+    assert!(mfd_keys::parse_chord("Ctrl+Banana").is_err());
+}
+
+#[test]
+fn test_rocker_corners() {
+    use crate::rocker::{select_rocker, could_lead_to_rocker};
+
+    assert_eq!(select_rocker(&[Direction::Up, Direction::Left]), Some(Rocker::Gain));
+    assert_eq!(select_rocker(&[Direction::Right, Direction::Up]), Some(Rocker::Sym));
+    assert_eq!(select_rocker(&[Direction::Down, Direction::Right]), Some(Rocker::Con));
+    assert_eq!(select_rocker(&[Direction::Left, Direction::Down]), Some(Rocker::Brt));
+    assert_eq!(select_rocker(&[Direction::Up, Direction::Down]), None);
+    assert!(!could_lead_to_rocker(&[Direction::Left, Direction::Right]));
+}
+
+#[test]
+fn test_rocker_layer() {
+    let _lock = setup_test_config_with(|config| {
+        config.profile.rocker.push(profile::RockerSettings {
+            mfd: MfdState::RightMfd,
+            rocker: Rocker::Brt,
+            up: Some("Ctrl+Shift+F1".to_string()),
+            down: Some("Ctrl+Shift+F2".to_string()),
+        });
+    });
+    assert_eq!(profile::rocker_chord(&MfdState::RightMfd, Rocker::Brt, false).as_deref(), Some("Ctrl+Shift+F2"));
+    assert_eq!(profile::rocker_chord(&MfdState::LeftMfd, Rocker::Brt, false), None);
+
+    // Long press down, then down and left for the bottom left corner
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::RightMfd };
+    simulate_button_event(InputEventType::ButtonDown, Direction::Down, &mut app_state, false);
+    simulate_button_event(InputEventType::LongPress, Direction::Down, &mut app_state, true);
+    assert!(matches!(app_state, AppState::SelectingRocker { mfd: MfdState::RightMfd, .. }));
+    simulate_button_event(InputEventType::ButtonUp, Direction::Down, &mut app_state, true);
+    assert!(matches!(app_state, AppState::SelectingRocker { .. }));
+
+    simulate_button_event(InputEventType::ButtonDown, Direction::Down, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Down, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Left, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Left, &mut app_state, false);
+
+    // Down rocks it down for as long as the hat is held, even past a long press
+    simulate_button_event(InputEventType::ButtonDown, Direction::Down, &mut app_state, false);
+    assert!(matches!(app_state, AppState::RockerPressed { mfd: MfdState::RightMfd, rocker: Rocker::Brt, up: false }));
+    simulate_button_event(InputEventType::LongPress, Direction::Down, &mut app_state, true);
+    assert!(matches!(app_state, AppState::RockerPressed { .. }));
+    simulate_button_event(InputEventType::ButtonUp, Direction::Down, &mut app_state, true);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+
+    // Two presses on the same axis isn't a corner
+    simulate_button_event(InputEventType::LongPress, Direction::Down, &mut app_state, true);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Left, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    assert!(matches!(app_state, AppState::InvalidSequence { .. }));
+}
+
+#[test]
+fn test_keypad_cursor_wraps() {
+    use crate::keypad::move_keypad_cursor;
+
+    assert_eq!(KEYPAD_KEYS[KEYPAD_HOME as usize], keypad::KeypadKey::Five);
+    assert_eq!(move_keypad_cursor(0, Direction::Left), 3);
+    assert_eq!(move_keypad_cursor(0, Direction::Up), 12);
+    assert_eq!(move_keypad_cursor(7, Direction::Right), 4);
+    assert_eq!(move_keypad_cursor(13, Direction::Down), 1);
+    assert_eq!(move_keypad_cursor(5, Direction::Down), 9);
+}
+
+#[test]
+fn test_keypad_mode() {
+    let _lock = setup_test_config_with(|config| {
+        config.profile.keypad.push(profile::KeypadSettings { key: keypad::KeypadKey::Entr, chord: "Ctrl+Shift+E".to_string() });
+    });
+    assert_eq!(profile::keypad_chord(keypad::KeypadKey::Entr).as_deref(), Some("Ctrl+Shift+E"));
+    assert_eq!(profile::keypad_chord(keypad::KeypadKey::Rcl), None);
+    let (cues, _) = record_sounds(&sound::SoundSettings::default());
+
+    // Unless it's turned on, long pressing towards the selected MFD just selects it again
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    simulate_button_event(InputEventType::ButtonDown, Direction::Left, &mut app_state, false);
+    simulate_button_event(InputEventType::LongPress, Direction::Left, &mut app_state, true);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Left, &mut app_state, true);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+    assert_eq!(std::mem::take(&mut *cues.lock().unwrap()), vec![Cue::MfdLeft]);
+
+    // When it is, the keypad opens, without the MFD's click
+    CONFIG.lock().unwrap().as_mut().unwrap().keypad_long_press = true;
+    simulate_button_event(InputEventType::ButtonDown, Direction::Left, &mut app_state, false);
+    simulate_button_event(InputEventType::LongPress, Direction::Left, &mut app_state, true);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Left, &mut app_state, true);
+    assert!(matches!(app_state, AppState::Keypad { mfd: MfdState::LeftMfd, position: KEYPAD_HOME, pressed: false, .. }));
+
+    // Short presses move to ENTR, a long press holds it until the hat is released
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Right, &mut app_state, false);
+    assert!(matches!(app_state, AppState::Keypad { position: 7, pressed: false, .. }));
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+    simulate_button_event(InputEventType::LongPress, Direction::Up, &mut app_state, true);
+    assert!(matches!(app_state, AppState::Keypad { position: 7, pressed: true, .. }));
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, &mut app_state, true);
+    assert!(matches!(app_state, AppState::Keypad { position: 7, pressed: false, .. }));
+
+    // A long press sideways goes back to that MFD
+    simulate_button_event(InputEventType::ButtonDown, Direction::Right, &mut app_state, false);
+    simulate_button_event(InputEventType::LongPress, Direction::Right, &mut app_state, true);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+}
+
+#[test]
+fn test_shift_layer_macros() {
+    let _lock = setup_test_config_with(|config| {
+        config.shift_layer = Some(config::ShiftLayer::Macros);
+        config.profile.macros.push(profile::MacroSettings { direction: Direction::Up, chord: "Ctrl+Shift+M".to_string() });
+    });
+    assert_eq!(profile::macro_chord(Direction::Up).as_deref(), Some("Ctrl+Shift+M"));
+
+    // Halfway through selecting an OSB on the right MFD
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::RightMfd };
+    simulate_button_event(InputEventType::ButtonDown, Direction::Left, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Left, &mut app_state, false);
+
+    handle_modifier(true, &mut app_state);
+    assert!(matches!(app_state, AppState::MacroLayer { pressed: None, underneath: Some(_) }));
+
+    // The hat sends macros instead of selecting OSBs, held past a long press
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+    assert!(matches!(app_state, AppState::MacroLayer { pressed: Some(Direction::Up), .. }));
+    simulate_button_event(InputEventType::LongPress, Direction::Up, &mut app_state, true);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, &mut app_state, true);
+    assert!(matches!(app_state, AppState::MacroLayer { pressed: None, .. }));
+
+    // Letting go of the modifier picks up where we were
+    handle_modifier(false, &mut app_state);
+    assert!(matches!(app_state, AppState::SelectingOSB { mfd: MfdState::RightMfd, side: Direction::Left, .. }));
+}
+
+#[test]
+fn test_shift_layer_keypad() {
+    let _lock = setup_test_config_with(|config| config.shift_layer = Some(config::ShiftLayer::Keypad));
+    output::release_all();
+
+    // Shifting while an OSB is held lets go of it first
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+    assert!(output::is_held(3));
+
+    handle_modifier(true, &mut app_state);
+    assert!(!output::is_held(3));
+    assert!(matches!(app_state, AppState::Keypad { mfd: MfdState::LeftMfd, position: KEYPAD_HOME, pressed: false, .. }));
+
+    // Hold a key and let go of the modifier first
+    simulate_button_event(InputEventType::ButtonDown, Direction::Down, &mut app_state, false);
+    simulate_button_event(InputEventType::LongPress, Direction::Down, &mut app_state, true);
+    assert!(matches!(app_state, AppState::Keypad { pressed: true, .. }));
+    handle_modifier(false, &mut app_state);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+
+    // The modifier does nothing outside of a shift layer
+    handle_modifier(false, &mut app_state);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+
+    // Or when there's no shift layer set up
+    CONFIG.lock().unwrap().as_mut().unwrap().shift_layer = None;
+    handle_modifier(true, &mut app_state);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+}
+
+#[test]
+fn test_extra_hat_runs_independently() {
+    let _lock = setup_test_config_with(|config| {
+        config.extra_hats.push(config::HatBindings {
+            up: (2, 1),
+            right: (2, 2),
+            down: (2, 3),
+            left: (2, 4),
+            role: HatRole::RightMfd,
+        });
+    });
+    assert_eq!(map_button_to_extra_hat(2, 3), Some((0, Direction::Down)));
+    assert_eq!(map_button_to_extra_hat(1, 3), None);
+    assert_eq!(map_button_to_direction(2, 3), None);
+
+    let mut hat = Hat::new(HatRole::RightMfd);
+    assert!(matches!(hat.state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+
+    // Both hats part way through a selection at the same time
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    simulate_button_event(InputEventType::ButtonDown, Direction::Up, &mut app_state, false);
+    simulate_button_event(InputEventType::ButtonUp, Direction::Up, &mut app_state, false);
+    handle_fixed_hat_event(InputEventType::ButtonDown, Direction::Down, &mut hat.state, false);
+    handle_fixed_hat_event(InputEventType::ButtonUp, Direction::Down, &mut hat.state, false);
+    assert!(matches!(app_state, AppState::SelectingOSB { mfd: MfdState::LeftMfd, side: Direction::Up, .. }));
+    assert!(matches!(hat.state, AppState::SelectingOSB { mfd: MfdState::RightMfd, side: Direction::Down, .. }));
+
+    handle_fixed_hat_event(InputEventType::ButtonDown, Direction::Down, &mut hat.state, false);
+    assert!(matches!(hat.state, AppState::OSBPressed { mfd: MfdState::RightMfd, osb_number: 33, .. }));
+    handle_fixed_hat_event(InputEventType::ButtonUp, Direction::Down, &mut hat.state, false);
+
+    // A long press sideways can't move it off its MFD
+    handle_fixed_hat_event(InputEventType::ButtonDown, Direction::Left, &mut hat.state, false);
+    handle_fixed_hat_event(InputEventType::LongPress, Direction::Left, &mut hat.state, true);
+    handle_fixed_hat_event(InputEventType::ButtonUp, Direction::Left, &mut hat.state, true);
+    assert!(matches!(hat.state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+    assert!(matches!(app_state, AppState::SelectingOSB { mfd: MfdState::LeftMfd, .. }));
+}
+
+#[test]
+fn test_keyboard_as_hat() {
+    use crossterm::event::KeyCode;
+
+    let up = keyboard::key_id(KeyCode::Up).unwrap();
+    let right = keyboard::key_id(KeyCode::Right).unwrap();
+    let _lock = setup_test_config_with(|config| {
+        config.button_bindings = ButtonBindings {
+            up: (KEYBOARD_DEVICE, up),
+            right: (KEYBOARD_DEVICE, right),
+            down: (KEYBOARD_DEVICE, keyboard::key_id(KeyCode::Down).unwrap()),
+            left: (KEYBOARD_DEVICE, keyboard::key_id(KeyCode::Left).unwrap()),
+            confirm: None,
+            modifier: None,
+        };
+    });
+    assert_eq!(keyboard::key_id(KeyCode::Char('W')), keyboard::key_id(KeyCode::Char('w')));
+    assert_ne!(keyboard::key_id(KeyCode::F(1)), keyboard::key_id(KeyCode::Char('1')));
+    assert_eq!(keyboard::key_id(KeyCode::Esc), None);
+    assert!(is_bound_button(KEYBOARD_DEVICE, up));
+    assert!(!is_bound_button(1, 1));
+
+    let mut ui = Ui::headless();
+    let mut inputs = test_inputs();
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+
+    // Key repeats while the arrow is held don't restart the long press
+    process_button(true, KEYBOARD_DEVICE, right, &mut app_state, &mut inputs, &mut ui);
+    let started = inputs.button_press_times[&(KEYBOARD_DEVICE, right)];
+    process_button(true, KEYBOARD_DEVICE, right, &mut app_state, &mut inputs, &mut ui);
+    assert_eq!(inputs.button_press_times[&(KEYBOARD_DEVICE, right)], started);
+
+    // Held long enough, it switches MFD just like a hat
+    inputs.button_press_times.insert((KEYBOARD_DEVICE, right), Instant::now() - LONGPRESS_DURATION);
+    check_long_presses(&mut app_state, &mut inputs, &mut ui);
+    process_button(false, KEYBOARD_DEVICE, right, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+
+    // Up, up presses OSB 23 until the key is released
+    process_button(true, KEYBOARD_DEVICE, up, &mut app_state, &mut inputs, &mut ui);
+    process_button(false, KEYBOARD_DEVICE, up, &mut app_state, &mut inputs, &mut ui);
+    process_button(true, KEYBOARD_DEVICE, up, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::OSBPressed { osb_number: 23, .. }));
+    process_button(false, KEYBOARD_DEVICE, up, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+
+    // Keys can be bound, but a held key repeating doesn't confirm itself
+    let keys = ['w', 'd', 's', 'a'].map(|c| keyboard::key_id(KeyCode::Char(c)).unwrap());
+    enter_binding_mode(&mut app_state, &mut ui).unwrap();
+    process_button(true, KEYBOARD_DEVICE, keys[0], &mut app_state, &mut inputs, &mut ui);
+    if let AppState::Binding(wizard) = &mut app_state {
+        wizard.detected_at -= Duration::from_secs(1);
+    }
+    process_button(true, KEYBOARD_DEVICE, keys[0], &mut app_state, &mut inputs, &mut ui);
+    let AppState::Binding(wizard) = &app_state else { panic!() };
+    assert_eq!(wizard.step(), binding::BindStep::Hat(Direction::Up));
+    process_button(false, KEYBOARD_DEVICE, keys[0], &mut app_state, &mut inputs, &mut ui);
+    for key in keys {
+        bind_button(KEYBOARD_DEVICE, key, &mut app_state, &mut inputs, &mut ui);
+    }
+    assert_eq!(map_button_to_direction(KEYBOARD_DEVICE, keys[3]), Some(Direction::Left));
+}
+
+// An in-memory MIDI port; the test writes bytes in and the source reads them out
+struct VirtualMidiPort(std::sync::Arc<Mutex<Vec<u8>>>);
+
+impl midi::MidiInput for VirtualMidiPort {
+    fn read(&mut self) -> io::Result<Vec<u8>> {
+        Ok(std::mem::take(&mut *self.0.lock().unwrap()))
+    }
+}
+
+fn virtual_midi() -> (std::sync::Arc<Mutex<Vec<u8>>>, midi::MidiSource) {
+    let bytes = std::sync::Arc::new(Mutex::new(Vec::new()));
+    (bytes.clone(), midi::MidiSource::new(Box::new(VirtualMidiPort(bytes))))
+}
+
+#[test]
+fn test_midi_messages_become_buttons() {
+    let (port, mut source) = virtual_midi();
+
+    // Note on, then running status with a clock byte in the middle, then a zero velocity note off
+    port.lock().unwrap().extend([0x91, 36, 100, 0xf8, 38, 90, 36, 0]);
+    assert_eq!(source.poll().unwrap(), vec![
+        (midi::note_button(1, 36), true),
+        (midi::note_button(1, 38), true),
+        (midi::note_button(1, 36), false),
+    ]);
+
+    // Sysex and program changes are skipped; a split message is picked up on the next read
+    port.lock().unwrap().extend([0xf0, 0x7e, 0x01, 0xf7, 0xc0, 5, 0x81]);
+    assert_eq!(source.poll().unwrap(), vec![]);
+    port.lock().unwrap().extend([38, 0]);
+    assert_eq!(source.poll().unwrap(), vec![(midi::note_button(1, 38), false)]);
+
+    // CCs only count when they cross half way
+    port.lock().unwrap().extend([0xb0, 7, 20, 7, 70, 7, 127, 7, 10]);
+    assert_eq!(source.poll().unwrap(), vec![
+        (midi::cc_button(0, 7), true),
+        (midi::cc_button(0, 7), false),
+    ]);
+
+    // Short messages come packed into a word on Windows, padded with zeros that aren't part
+    // of them; active sensing between two notes mustn't turn into a running status note
+    for packed in [0x00642490u32, 0xfe, 0xf1, 0xf6, 0x00002480] {
+        let bytes = packed.to_le_bytes();
+        port.lock().unwrap().extend(&bytes[..midi::message_length(bytes[0])]);
+    }
+    assert_eq!(source.poll().unwrap(), vec![
+        (midi::note_button(0, 36), true),
+        (midi::note_button(0, 36), false),
+    ]);
+}
+
+#[test]
+fn test_midi_actions_and_learning() {
+    let _lock = setup_test_config_with(|config| {
+        config.midi = vec![
+            midi::MidiMapping { channel: None, note: Some(40), cc: None, action: ButtonAction::Osb(27) },
+            midi::MidiMapping { channel: Some(2), note: None, cc: Some(1), action: ButtonAction::SelectMfd(MfdState::RightMfd) },
+            midi::MidiMapping { channel: None, note: None, cc: None, action: ButtonAction::Macro("Ctrl+F1".to_string()) },
+        ];
+    });
+    let mut ui = Ui::headless();
+    let mut inputs = test_inputs();
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    let (port, mut source) = virtual_midi();
+    let mut play = |bytes: &[u8], app_state: &mut AppState| {
+        port.lock().unwrap().extend(bytes);
+        for (button_id, pressed) in source.poll().unwrap() {
+            process_button(pressed, MIDI_DEVICE, button_id, app_state, &mut inputs, &mut ui);
+        }
+    };
+
+    // Unmapped pads are left alone until learning is asked for
+    play(&[0x90, 60, 100, 0x80, 60, 0], &mut app_state);
+    assert!(midi_action(midi::note_button(0, 60)).is_none());
+    enter_learn_mode(&mut app_state, &mut Ui::headless());
+    assert!(matches!(&app_state, AppState::Learning { next, .. } if next == "MIDI 3"));
+
+    // Then the first unmapped pad is learned by the macro, which then answers to it
+    play(&[0x90, 40, 100, 0x80, 40, 0], &mut app_state);
+    assert!(matches!(app_state, AppState::Learning { .. }));
+    play(&[0x90, 60, 100, 0x80, 60, 0], &mut app_state);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+    {
+        let config = CONFIG.lock().unwrap();
+        let learned = &config.as_ref().unwrap().midi[2];
+        assert_eq!((learned.channel, learned.note, learned.cc), (Some(1), Some(60), None));
+    }
+    assert!(midi_action(midi::note_button(0, 60)).is_some());
+    assert!(midi_action(midi::note_button(0, 61)).is_none());
+    enter_learn_mode(&mut app_state, &mut Ui::headless());
+    assert!(matches!(app_state, AppState::WaitingForSide { .. }));
+
+    // The CC is on the wrong channel, so nothing happens
+    play(&[0xb0, 1, 127, 0xb0, 1, 0], &mut app_state);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+    play(&[0xb1, 1, 127, 0xb1, 1, 0], &mut app_state);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+
+    // OSB 27 is held for as long as the pad is
+    play(&[0x99, 40, 100], &mut app_state);
+    assert!(matches!(app_state, AppState::OSBPressed { osb_number: 27, .. }));
+    play(&[0x89, 40, 0], &mut app_state);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+}
+
+#[test]
+fn test_midi_binding_learns_hat() {
+    let _lock = setup_test_config();
+    let mut ui = Ui::headless();
+    let mut inputs = test_inputs();
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    enter_binding_mode(&mut app_state, &mut ui).unwrap();
+    for note in [48, 49, 50, 51] {
+        bind_button(MIDI_DEVICE, midi::note_button(0, note), &mut app_state, &mut inputs, &mut ui);
+    }
+
+    // Without a shift layer there's no Shift step, so the pads drive the hat straight away
+    assert!(matches!(app_state, AppState::WaitingForSide { .. }));
+    assert_eq!(map_button_to_direction(MIDI_DEVICE, midi::note_button(0, 48)), Some(Direction::Up));
+    assert_eq!(map_button_to_direction(MIDI_DEVICE, midi::note_button(0, 51)), Some(Direction::Left));
+    process_button(true, MIDI_DEVICE, midi::note_button(0, 48), &mut app_state, &mut inputs, &mut ui);
+    process_button(false, MIDI_DEVICE, midi::note_button(0, 48), &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::SelectingOSB { side: Direction::Up, .. }));
+}
+
+#[test]
+fn test_direct_buttons() {
+    let _lock = setup_test_config_with(|config| {
+        config.direct_buttons = vec![
+            config::DirectButton { button: Some((2, 12)), action: ButtonAction::Osb(12) },
+            config::DirectButton { button: Some((2, 21)), action: ButtonAction::SelectMfd(MfdState::RightMfd) },
+            config::DirectButton { button: None, action: ButtonAction::Osb(33) },
+        ];
+    });
+    let mut ui = Ui::headless();
+    let mut inputs = test_inputs();
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::RightMfd };
+    assert!(is_bound_button(2, 12));
+
+    // OSB 12 is on the left MFD, but there's no need to select it first
+    process_button(true, 2, 12, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::OSBPressed { osb_number: 12, .. }));
+    assert!(output::is_held(12));
+    process_button(false, 2, 12, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+    assert!(!output::is_held(12));
+    assert_eq!(history::last(&MfdState::LeftMfd), Some(12));
+
+    // MFD select only switches when nothing is held
+    process_button(true, 2, 21, &mut app_state, &mut inputs, &mut ui);
+    process_button(false, 2, 21, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+    app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    process_button(true, 2, 21, &mut app_state, &mut inputs, &mut ui);
+    process_button(false, 2, 21, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+
+    // Unbound buttons do nothing outside of learning
+    app_state = AppState::WaitingForSide { mfd: MfdState::RightMfd };
+    process_button(true, 3, 7, &mut app_state, &mut inputs, &mut ui);
+    process_button(false, 3, 7, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+    assert_eq!(direct_action(3, 7), None);
+
+    // Hat buttons are never learned; the next unbound button is
+    enter_learn_mode(&mut app_state, &mut ui);
+    assert!(matches!(&app_state, AppState::Learning { next, .. } if next == "Direct button 3"));
+    process_button(true, 1, 1, &mut app_state, &mut inputs, &mut ui);
+    process_button(false, 1, 1, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::Learning { .. }));
+    process_button(true, 3, 7, &mut app_state, &mut inputs, &mut ui);
+    process_button(false, 3, 7, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+    assert_eq!(direct_action(3, 7), Some((2, ButtonAction::Osb(33))));
+    process_button(true, 3, 7, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::OSBPressed { osb_number: 33, .. }));
+    process_button(false, 3, 7, &mut app_state, &mut inputs, &mut ui);
+}
+
+#[test]
+fn test_binding_wizard() {
+    let _lock = setup_test_config_with(|config| {
+        config.shift_layer = Some(config::ShiftLayer::Keypad);
+        config.direct_buttons = vec![config::DirectButton { button: Some((2, 9)), action: ButtonAction::Osb(1) }];
+        config.midi = vec![midi::MidiMapping { channel: None, note: Some(40), cc: None, action: ButtonAction::Osb(27) }];
+    });
+    let mut ui = Ui::headless();
+    let mut inputs = test_inputs();
+    inputs.device_names.insert(2, "Button Box".to_string());
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::RightMfd };
+    let step = |app_state: &AppState| match app_state {
+        AppState::Binding(wizard) => Some(wizard.step()),
+        _ => None,
+    };
+
+    // Nothing is kept until the last step, so cancelling puts everything back
+    enter_binding_mode(&mut app_state, &mut ui).unwrap();
+    bind_button(2, 1, &mut app_state, &mut inputs, &mut ui);
+    assert_eq!(step(&app_state), Some(binding::BindStep::Hat(Direction::Right)));
+    cancel_binding(&mut app_state);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+    assert_eq!(map_button_to_direction(1, 1), Some(Direction::Up));
+
+    // The first press is shown for confirmation; a different button replaces it
+    enter_binding_mode(&mut app_state, &mut ui).unwrap();
+    process_button(true, 2, 1, &mut app_state, &mut inputs, &mut ui);
+    let AppState::Binding(wizard) = &app_state else { panic!() };
+    assert_eq!(wizard.pending, Some(((2, 1), "Button Box button 1".to_string())));
+    bind_button(2, 2, &mut app_state, &mut inputs, &mut ui);
+    assert_eq!(step(&app_state), Some(binding::BindStep::Hat(Direction::Right)));
+
+    // Buttons already in use are turned down
+    bind_button(2, 2, &mut app_state, &mut inputs, &mut ui);
+    bind_button(2, 9, &mut app_state, &mut inputs, &mut ui);
+    let AppState::Binding(wizard) = &mut app_state else { panic!() };
+    assert_eq!(wizard.step(), binding::BindStep::Hat(Direction::Right));
+    assert_eq!(wizard.message.as_deref(), Some("Button Box button 9 is already used for direct button 1"));
+    bind_button(MIDI_DEVICE, midi::note_button(0, 40), &mut app_state, &mut inputs, &mut ui);
+    let AppState::Binding(wizard) = &mut app_state else { panic!() };
+    assert_eq!(wizard.step(), binding::BindStep::Hat(Direction::Right));
+    assert_eq!(wizard.message.as_deref(), Some("MIDI note 40 (channel 1) is already used for MIDI mapping 1"));
+
+    // Back a step to redo Up
+    wizard.back();
+    assert_eq!(wizard.step(), binding::BindStep::Hat(Direction::Up));
+    for button in [5, 6, 7, 8] {
+        bind_button(2, button, &mut app_state, &mut inputs, &mut ui);
+    }
+    assert_eq!(step(&app_state), Some(binding::BindStep::Modifier));
+
+    // A jittery button is never bound, however long it's left
+    for _ in 0..4 {
+        process_button(true, 3, 1, &mut app_state, &mut inputs, &mut ui);
+        process_button(false, 3, 1, &mut app_state, &mut inputs, &mut ui);
+    }
+    bind_button(3, 1, &mut app_state, &mut inputs, &mut ui);
+    let AppState::Binding(wizard) = &app_state else { panic!() };
+    assert_eq!(wizard.pending, None);
+    assert_eq!(wizard.step(), binding::BindStep::Modifier);
+
+    // Skipping the modifier finishes and saves, back on the MFD we started from
+    process_button(true, 2, 5, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+    process_button(false, 2, 5, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::RightMfd }));
+    assert_eq!(map_button_to_direction(2, 5), Some(Direction::Up));
+    assert_eq!(map_button_to_direction(2, 8), Some(Direction::Left));
+    assert_eq!(map_button_to_direction(1, 1), None);
+    assert!(!is_modifier_button(2, 5));
+}
+
+#[test]
+fn test_config_path_and_migration() {
+    let _lock = setup_test_config();
+    let dir = std::env::temp_dir().join(format!("superhat-migrate-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // An old config in the working directory moves into the new place
+    let old_path = dir.join("superhat.cfg");
+    let path = dir.join("config").join("superhat").join("superhat.cfg");
+    let old_config = Config { selected_mfd: MfdState::RightMfd, ..Default::default() };
+    std::fs::write(&old_path, toml::to_string(&old_config).unwrap()).unwrap();
+    config::migrate_config(&old_path, &path);
+    assert!(path.exists());
+    assert!(!old_path.exists());
+
+    // ...but never over one that's already there
+    std::fs::write(&old_path, "sound_enabled = false").unwrap();
+    config::migrate_config(&old_path, &path);
+    config::set_config_path(path.clone());
+    assert_eq!(config::load_config().unwrap().selected_mfd, MfdState::RightMfd);
+
+    // Saves go through a temporary file that doesn't stay behind
+    save_config(&Config::default());
+    assert_eq!(config::load_config().unwrap().selected_mfd, MfdState::LeftMfd);
+    assert!(!path.with_extension("cfg.tmp").exists());
+    assert_eq!(std::fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_config_versions_and_errors() {
+    // Old files have no version and only the original keys; everything else gets a default
+    let old = "selected_mfd = \"RightMfd\"\n[button_bindings]\nup = [1, 5]\nright = [1, 6]\ndown = [1, 7]\nleft = [1, 8]\n";
+    let config = config::parse_config(old).unwrap();
+    assert_eq!(config.version, config::CONFIG_VERSION);
+    assert_eq!(config.selected_mfd, MfdState::RightMfd);
+    assert_eq!(config.button_bindings.left, (1, 8));
+    assert!(config.sound_enabled);
+    assert_eq!(config.button_bindings.confirm, None);
+
+    // What we save comes back the same
+    let saved = toml::to_string(&config).unwrap();
+    assert_eq!(toml::to_string(&config::parse_config(&saved).unwrap()).unwrap(), saved);
+
+    // Mistakes are reported with where they are
+    let typo = format!("version = {}\nsound_enabled = flase\n", config::CONFIG_VERSION);
+    let err = config::parse_config(&typo).unwrap_err();
+    assert!(err.starts_with("Config error at line 2, column 17"), "{}", err);
+    assert!(config::parse_config("version = 99").unwrap_err().contains("newer"));
+}
+
+#[test]
+fn test_broken_config_is_backed_up() {
+    let _lock = setup_test_config();
+    let dir = std::env::temp_dir().join(format!("superhat-broken-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("superhat.cfg");
+    config::set_config_path(path.clone());
+
+    std::fs::write(&path, "[button_bindings\nup = [1, 5]").unwrap();
+    let err = config::load_config().unwrap_err();
+    assert!(err.contains("line 1"), "{}", err);
+    assert!(err.contains("superhat.cfg.bak"), "{}", err);
+    assert_eq!(std::fs::read_to_string(path.with_extension("cfg.bak")).unwrap(), "[button_bindings\nup = [1, 5]");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_config_reload() {
+    let _lock = setup_test_config();
+    let dir = std::env::temp_dir().join(format!("superhat-reload-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("superhat.cfg");
+    config::set_config_path(path.clone());
+    save_config(&Config::default());
+
+    // Our own saves aren't edits
+    let mut watcher = config::ConfigWatcher::new(path.clone());
+    assert!(watcher.poll().is_none());
+    save_config(&Config::default());
+    assert!(watcher.poll().is_none());
+
+    // File times can be coarse, so make sure each edit looks newer
+    let edit = |text: &str, seconds: u64| {
+        std::fs::write(&path, text).unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(seconds)).unwrap();
+    };
+    edit("selection_mode = \"Cursor\"", 10);
+    let config = watcher.poll().unwrap().unwrap();
+    assert_eq!(config.selection_mode, SelectionMode::Cursor);
+    edit("selection_mode = Cursor", 20);
+    assert!(watcher.poll().unwrap().unwrap_err().contains("keeping the current settings"));
+    assert!(watcher.poll().is_none());
+
+    // So is one that parses but has problems, without being backed up
+    let _ = std::fs::remove_file(path.with_extension("cfg.bak"));
+    edit("[[direct_buttons]]\naction = { Osb = 41 }", 30);
+    assert_eq!(watcher.poll().unwrap().unwrap_err(), "Direct button 1: OSBs go from 1 to 40 - keeping the current settings");
+    assert!(!path.with_extension("cfg.bak").exists());
+
+    // A held OSB is let go, and a state from the old selection mode is swapped for the new one
+    let mut inputs = test_inputs();
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::RightMfd };
+    replay_osb(28, &mut app_state);
+    assert!(output::is_held(28));
+    let mut broken = config.clone();
+    broken.profile.macros.push(profile::MacroSettings { direction: Direction::Up, chord: "Ctrl+Nope".to_string() });
+    assert!(reload_config(broken, &mut app_state, &mut inputs, &mut None).is_err());
+    assert!(output::is_held(28));
+    reload_config(config, &mut app_state, &mut inputs, &mut None).unwrap();
+    assert!(!output::is_held(28));
+    assert!(matches!(app_state, AppState::CursorRoaming { mfd: MfdState::RightMfd, .. }));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn test_profile(name: &str) -> config::NamedProfile {
+    config::NamedProfile { name: name.to_string(), ..Default::default() }
+}
+
+#[test]
+fn test_named_profiles() {
+    let _lock = setup_test_config_with(|config| {
+        let mut dcs = test_profile("DCS F-16");
+        dcs.button_bindings = Some(ButtonBindings { up: (2, 1), right: (2, 2), down: (2, 3), left: (2, 4), ..Default::default() });
+        dcs.profile.osb.push(profile::OsbSettings {
+            osb: 1,
+            chord: Some("Ctrl+Shift+F1".to_string()),
+            label: Some("MENU".to_string()),
+            ..Default::default()
+        });
+        config.profiles = vec![dcs, test_profile("DCS A-10")];
+    });
+    assert_eq!(CONFIG.lock().unwrap().as_ref().unwrap().profile_name(), config::DEFAULT_PROFILE);
+    assert!(profile::osb_chord(1).is_none());
+
+    // A long press up only cycles the profiles when that's turned on
+    let mut ui = Ui::headless();
+    let mut inputs = test_inputs();
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    let long_press_up = |app_state: &mut AppState, inputs: &mut Inputs, ui: &mut Ui| {
+        process_button(true, 1, 1, app_state, inputs, ui);
+        inputs.button_press_times.insert((1, 1), Instant::now() - LONGPRESS_DURATION);
+        check_long_presses(app_state, inputs, ui);
+        process_button(false, 1, 1, app_state, inputs, ui);
+    };
+    long_press_up(&mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+    assert_eq!(CONFIG.lock().unwrap().as_ref().unwrap().profile_name(), config::DEFAULT_PROFILE);
+
+    // When it is, each one's bindings and key table are picked up
+    CONFIG.lock().unwrap().as_mut().unwrap().profile_long_press = true;
+    long_press_up(&mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::WaitingForSide { mfd: MfdState::LeftMfd }));
+    assert_eq!(CONFIG.lock().unwrap().as_ref().unwrap().profile_name(), "DCS F-16");
+    assert_eq!(profile::osb_chord(1).as_deref(), Some("Ctrl+Shift+F1"));
+    assert_eq!(CONFIG.lock().unwrap().as_ref().unwrap().key_profile().osb_label(1), Some("MENU"));
+    assert_eq!(map_button_to_direction(2, 1), Some(Direction::Up));
+    assert_eq!(map_button_to_direction(1, 1), None);
+
+    // Without bindings of its own a profile uses the main ones, and rebinding changes those
+    cycle_profile();
+    let mut config = CONFIG.lock().unwrap().clone().unwrap();
+    assert_eq!(config.profile_name(), "DCS A-10");
+    assert_eq!(map_button_to_direction(1, 1), Some(Direction::Up));
+    config.bindings_mut().up = (3, 1);
+    assert_eq!(config.button_bindings.up, (3, 1));
+
+    cycle_profile();
+    assert_eq!(CONFIG.lock().unwrap().as_ref().unwrap().profile_name(), config::DEFAULT_PROFILE);
+
+    // A profile that's since been removed falls back to the main settings
+    switch_profile(Some("Falcon 4".to_string()));
+    let config = CONFIG.lock().unwrap().clone().unwrap();
+    assert_eq!(config.profile_name(), config::DEFAULT_PROFILE);
+    assert_eq!(config.next_profile().as_deref(), Some("DCS F-16"));
+}
+
+#[test]
+fn test_profile_detection() {
+    let mut viper = test_profile("Viper");
+    viper.aircraft = vec!["F-16C_50".to_string()];
+    let mut dcs = test_profile("DCS");
+    dcs.processes = vec!["DCS.exe".to_string()];
+    let mut bms = test_profile("BMS");
+    bms.processes = vec!["Falcon BMS.exe".to_string()];
+    let profiles = vec![viper, dcs, bms];
+
+    let running = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+    assert_eq!(detect::detect(&profiles, &running(&["explorer.exe"]), None), None);
+    assert_eq!(detect::detect(&profiles, &running(&["falcon bms.exe"]), None).as_deref(), Some("BMS"));
+    assert_eq!(detect::detect(&profiles, &running(&["DCS.exe"]), Some("A-10C")).as_deref(), Some("DCS"));
+    // The aircraft is the better guess
+    assert_eq!(detect::detect(&profiles, &running(&["DCS.exe"]), Some("F-16C_50")).as_deref(), Some("Viper"));
+
+    // The aircraft comes in over UDP, and only a change is reported
+    let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut detector = detect::ProfileDetector::default();
+    detector.listen(Some(port)).unwrap();
+    assert_eq!(detector.port(), Some(port));
+    assert_eq!(detector.poll(&profiles), None);
+    let feed = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    feed.send_to(b"F-16C_50\n", ("127.0.0.1", port)).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(detector.poll(&profiles).as_deref(), Some("Viper"));
+    assert_eq!(detector.poll(&profiles), None);
+}
+
+#[test]
+fn test_command_line() {
+    let parse = |line: &str| cli::parse(line.split_whitespace().map(str::to_string));
+    assert_eq!(parse("").unwrap().command, cli::Command::Run);
+    assert_eq!(parse("bind --profile Viper").unwrap().profile.as_deref(), Some("Viper"));
+    assert_eq!(parse("press right 3").unwrap().command, cli::Command::Press(23));
+
+    // Options go either side of the command
+    let options = parse("--config my.cfg export-dcs out.lua --output joystick --headless").unwrap();
+    assert_eq!(options.command, cli::Command::ExportDcs(Some("out.lua".into())));
+    assert_eq!(options.config, Some("my.cfg".into()));
+    assert_eq!(options.output, Some(config::OutputMode::Joystick));
+    assert!(options.headless);
+
+    assert!(parse("press left 21").is_err());
+    assert!(parse("press up 3").is_err());
+    assert!(parse("import-bms-keys").is_err());
+    assert!(parse("--config").is_err());
+    assert!(parse("--output vjoy").is_err());
+    assert!(parse("show-config extra").is_err());
+    assert!(parse("fly").is_err());
+    assert_eq!(parse("fly --help").unwrap().command, cli::Command::Help);
+
+    let mut config = Config::default();
+    config.profiles.push(config::NamedProfile { name: "DCS F-16".to_string(), ..Default::default() });
+    cli::select_profile(&mut config, "dcs f-16").unwrap();
+    assert_eq!(config.active_profile.as_deref(), Some("DCS F-16"));
+    cli::select_profile(&mut config, "default").unwrap();
+    assert_eq!(config.active_profile, None);
+    assert!(cli::select_profile(&mut config, "Hornet").is_err());
+}
+
+#[test]
+fn test_key_files() {
+    let key_file = "\
+SimDoNothing -1 0 0XFFFFFFFF 0 0 0 -1 \"======== 4.05     LEFT MFD ========\"
+SimCBEOSB_1L 126 0 0x2 6 0 0 1 \"LMFD: OSB-1 Button - Push\"
+SimCBEOSB_11L 126 0 0x4F 6 0 0 1 \"LMFD: OSB-11 Button - Push\"
+SimCBEOSB_2R 126 0 0x3B 3 0 0 1 \"RMFD: OSB-2 Button - Push\"
+SimCBEOSB_3R 126 0 0x2 6 0x1D 2 1 \"RMFD: OSB-3 Button - Push\"
+SimCBEOSB_4R 126 0 0XFFFFFFFF 0 0 0 1 \"RMFD: OSB-4 Button - Push\"
+";
+    let (chords, skipped) = keyfiles::parse_bms_keys(key_file);
+    assert_eq!(chords, vec![
+        (1, "Ctrl+Alt+1".to_string()),
+        (11, "Ctrl+Alt+Numpad1".to_string()),
+        (22, "Ctrl+Shift+F1".to_string()),
+    ]);
+    assert_eq!(skipped.len(), 1);
+    assert!(skipped[0].starts_with("SimCBEOSB_3R"));
+    for (osb, chord) in &chords[..2] {
+        assert_eq!(mfd_keys::MFD_KEYS[*osb as usize - 1], chord);
+    }
+
+    let lua = keyfiles::dcs_keyboard_diff(|osb| (osb == 40).then(|| "Ctrl+Shift+F12".to_string()));
+    assert!(lua.contains("[\"d3001pnilu3001cd24vd1vpnilvu0\"]"));
+    assert!(lua.contains("[\"key\"] = \"Num9\",\n\t\t\t\t\t[\"reformers\"] = {\n\t\t\t\t\t\t[1] = \"LShift\",\n\t\t\t\t\t\t[2] = \"LAlt\","));
+    assert!(lua.contains("[\"key\"] = \"F12\""));
+    assert!(lua.contains("[\"name\"] = \"Right MFD OSB 20\""));
+    assert!(lua.ends_with("return diff\n"));
+}
+
+#[test]
+fn test_import_keeps_active_profile() {
+    let _lock = setup_test_config();
+    let dir = std::env::temp_dir().join(format!("superhat-import-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    config::set_config_path(dir.join("superhat.cfg"));
+    let mut config = Config::default();
+    config.profiles.push(test_profile("DCS F-16"));
+    save_config(&config);
+    let key_file = dir.join("BMS.key");
+    std::fs::write(&key_file, "SimCBEOSB_2R 126 0 0x3B 3 0 0 1 \"RMFD: OSB-2 Button - Push\"\n").unwrap();
+
+    // The keys land in the named profile, but the default one stays active
+    let options = cli::parse(["import-bms-keys".to_string(), key_file.display().to_string()]).unwrap();
+    cli::run_command(&cli::Options { profile: Some("dcs f-16".to_string()), ..options }).unwrap();
+    let saved = config::load_config().unwrap();
+    assert_eq!(saved.active_profile, None);
+    assert_eq!(saved.profiles[0].profile.osb_chord(22), Some("Ctrl+Shift+F1"));
+    assert_eq!(saved.profile.osb_chord(22), None);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_profile_problems() {
+    let mut key_profile = profile::KeyProfile::default();
+    assert!(key_profile.problems().is_empty());
+    key_profile.osb.push(profile::OsbSettings { osb: 41, label: Some("TOO LONG".to_string()), ..Default::default() });
+    key_profile.osb.push(profile::OsbSettings { osb: 2, chord: Some("Ctrl+Hyper+1".to_string()), ..Default::default() });
+    key_profile.macros.push(profile::MacroSettings { direction: Direction::Up, chord: "Ctrl+Shift+F1".to_string() });
+    let problems = key_profile.problems();
+    assert_eq!(problems.len(), 3, "{:?}", problems);
+    assert!(problems[0].starts_with("OSB 2: Unknown key 'Hyper'"));
+
+    // The whole config: every profile and button mapping, in one line for the status bar
+    let mut config = Config::default();
+    let mut named = test_profile("DCS F-16");
+    named.profile = key_profile;
+    config.profiles.push(named);
+    config.direct_buttons.push(config::DirectButton { button: None, action: ButtonAction::Macro("Ctrl+Nope".to_string()) });
+    config.direct_buttons.push(config::DirectButton { button: None, action: ButtonAction::Osb(0) });
+    let problems = config.problems();
+    assert_eq!(problems.len(), 5, "{:?}", problems);
+    assert!(problems[0].starts_with("DCS F-16: OSB 2: Unknown key 'Hyper'"));
+    assert_eq!(problems[4], "Direct button 2: OSBs go from 1 to 40");
+    assert_eq!(config::problems_message(&problems[4..]), Some(problems[4].clone()));
+    assert!(config::problems_message(&problems).unwrap().ends_with("(and 4 more)"));
+    assert_eq!(config::problems_message(&[]), None);
+}
+
+#[test]
+fn test_control_requests() {
+    assert_eq!(control::parse_request("press right 3\n"), Ok(control::Request::Press(23)));
+    assert_eq!(control::parse_request("profile DCS F-16"), Ok(control::Request::Profile(Some("DCS F-16".to_string()))));
+    assert_eq!(control::parse_request("profile"), Ok(control::Request::Profile(None)));
+    assert_eq!(control::parse_request("status"), Ok(control::Request::Status));
+    assert!(control::parse_request("press left 30").is_err());
+    assert!(control::parse_request("quit now").is_err());
+    assert!(control::parse_request("fly").is_err());
+
+    let _lock = setup_test_config_with(|config| {
+        config.profiles.push(config::NamedProfile { name: "DCS F-16".to_string(), ..Default::default() });
+    });
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    let mut inputs = test_inputs();
+    let mut running = true;
+    let mut ui = Ui::headless();
+    let mut handle = |request: &str, app_state: &mut AppState, running: &mut bool| {
+        handle_request(control::parse_request(request).unwrap(), app_state, &mut inputs, &mut None, &mut ui, running)
+    };
+
+    // A press is a tap, let go of by the output on its own
+    handle("press left 5", &mut app_state, &mut running);
+    assert!(output::is_held(5));
+    std::thread::sleep(TAP_DURATION + Duration::from_millis(10));
+    output::tick();
+    assert!(!output::is_held(5));
+
+    assert_eq!(handle("profile dcs f-16", &mut app_state, &mut running), "Profile DCS F-16");
+    assert_eq!(handle("profile Hornet", &mut app_state, &mut running), "There's no profile called 'Hornet'");
+    assert_eq!(handle("status", &mut app_state, &mut running), format!("DCS F-16: {}", ui::status_text(&app_state)));
+    assert_eq!(handle("show", &mut app_state, &mut running), "Superhat is already running");
+    handle("quit", &mut app_state, &mut running);
+    assert!(!running);
+}
+
+#[test]
+fn test_control_socket() {
+    use std::io::{Read, Write};
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut server = control::ControlServer::bind(port).unwrap();
+    assert_eq!(server.port(), Some(port));
+
+    let mut client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client.write_all(b"status\nfly\npress le").unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    let mut requests = Vec::new();
+    server.poll(|request| {
+        requests.push(request.clone());
+        match request {
+            Ok(_) => "ok".to_string(),
+            Err(err) => err,
+        }
+    });
+    // The half-sent line waits for the rest of it
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0], Ok(control::Request::Status));
+    assert!(requests[1].is_err());
+
+    client.write_all(b"ft 1\n").unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    server.poll(|request| {
+        assert_eq!(request, Ok(control::Request::Press(1)));
+        "ok".to_string()
+    });
+
+    client.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let mut replies = String::new();
+    let mut buf = [0; 256];
+    while replies.matches('\n').count() < 3 {
+        let len = client.read(&mut buf).unwrap();
+        replies.push_str(std::str::from_utf8(&buf[..len]).unwrap());
+    }
+    assert!(replies.starts_with("ok\nUnknown request 'fly'"));
+    assert!(replies.ends_with("ok\n"));
+}
+
+#[test]
+fn test_headless_ui() {
+    let _lock = setup_test_config();
+    let mut ui = Ui::headless();
+    assert!(ui.is_headless());
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    ui.update(&app_state).unwrap();
+    ui.set_message("Reloaded config".to_string(), false);
+
+    // Buttons still go through the whole state machine
+    let mut inputs = test_inputs();
+    process_button(true, 1, 1, &mut app_state, &mut inputs, &mut ui);
+    process_button(false, 1, 1, &mut app_state, &mut inputs, &mut ui);
+    assert!(matches!(app_state, AppState::SelectingOSB { .. }));
+}
+
+#[test]
+fn test_forwarded_requests() {
+    assert_eq!(instance::forwarded_request(&cli::Command::Run), Some("show".to_string()));
+    assert_eq!(instance::forwarded_request(&cli::Command::Bind), Some("show".to_string()));
+    assert_eq!(instance::forwarded_request(&cli::Command::Press(23)), Some("press right 3".to_string()));
+    assert_eq!(instance::forwarded_request(&cli::Command::ShowConfig), None);
+    assert_eq!(control::parse_request("show"), Ok(control::Request::Show));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_single_instance() {
+    let dir = std::env::temp_dir().join(format!("superhat-instance-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let instance::Claim::First(mut guard) = instance::Guard::claim_in(&dir, "Superhat").unwrap() else {
+        panic!("nothing else should be running in the test directory");
+    };
+    assert!(matches!(instance::Guard::claim_in(&dir, "Superhat").unwrap(), instance::Claim::Running));
+
+    // A second launch's request is answered by the first
+    let forward_dir = dir.clone();
+    let second = std::thread::spawn(move || instance::Guard::forward_in(&forward_dir, "Superhat", "press left 3"));
+    let mut requests = Vec::new();
+    let start = Instant::now();
+    while requests.is_empty() && start.elapsed() < Duration::from_secs(2) {
+        guard.poll(|request| {
+            requests.push(request);
+            "Pressed OSB 3".to_string()
+        });
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(requests, vec![Ok(control::Request::Press(3))]);
+    assert_eq!(second.join().unwrap().unwrap(), "Pressed OSB 3");
+
+    // Once the first has gone the next launch takes over
+    drop(guard);
+    assert!(matches!(instance::Guard::claim_in(&dir, "Superhat").unwrap(), instance::Claim::First(_)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+type Recorded<T> = std::sync::Arc<Mutex<Vec<T>>>;
+
+struct RecordingSound {
+    cues: Recorded<Cue>,
+    words: Recorded<String>,
+}
+
+impl sound::SoundBackend for RecordingSound {
+    fn play(&mut self, cue: Cue, _wav: std::sync::Arc<[u8]>) {
+        self.cues.lock().unwrap().push(cue);
+    }
+
+    fn say(&mut self, word: &str, _wav: std::sync::Arc<[u8]>) {
+        self.words.lock().unwrap().push(word.to_string());
+    }
+}
+
+// The cues played, and the words said, from here on
+fn record_sounds(settings: &sound::SoundSettings) -> (Recorded<Cue>, Recorded<String>) {
+    let recording = RecordingSound { cues: Default::default(), words: Default::default() };
+    let recorded = (recording.cues.clone(), recording.words.clone());
+    sound::set_backend(Some(Box::new(recording)));
+    assert_eq!(sound::load(settings, &std::env::temp_dir()), Vec::<String>::new());
+    *SOUND_ENABLED.lock().unwrap() = true;
+    recorded
+}
+
+#[test]
+fn test_click_sounds() {
+    let _lock = setup_test_config();
+    let (played, _) = record_sounds(&sound::SoundSettings::default());
+
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    select_mfd(Direction::Right, &mut app_state);
+    select_mfd(Direction::Left, &mut app_state);
+    assert_eq!(*played.lock().unwrap(), vec![Cue::MfdRight, Cue::MfdLeft]);
+
+    *SOUND_ENABLED.lock().unwrap() = false;
+    select_mfd(Direction::Right, &mut app_state);
+    assert_eq!(played.lock().unwrap().len(), 2);
+    *SOUND_ENABLED.lock().unwrap() = true;
+    sound::set_backend(None);
+}
+
+#[test]
+fn test_transition_cues() {
+    let _lock = setup_test_config();
+    let mut settings = sound::SoundSettings::default();
+    settings.timeout.enabled = false;
+    let (played, _) = record_sounds(&settings);
+    let take = || std::mem::take(&mut *played.lock().unwrap());
+
+    // Side, a step, then the OSB
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::LeftMfd };
+    handle_short_press(Direction::Left, &mut app_state);
+    handle_short_press(Direction::Up, &mut app_state);
+    handle_short_press(Direction::Left, &mut app_state);
+    assert!(matches!(app_state, AppState::OSBPressed { .. }));
+    assert_eq!(take(), vec![Cue::Side, Cue::Moved, Cue::Osb]);
+    handle_release(&mut app_state);
+
+    handle_short_press(Direction::Left, &mut app_state);
+    handle_short_press(Direction::Right, &mut app_state);
+    assert!(matches!(app_state, AppState::InvalidSequence { .. }));
+    assert_eq!(take(), vec![Cue::Side, Cue::Invalid]);
+    handle_release(&mut app_state);
+
+    // Turned off in the settings
+    handle_short_press(Direction::Left, &mut app_state);
+    if let AppState::SelectingOSB { last_input_time, .. } = &mut app_state {
+        *last_input_time -= TIMEOUT_DURATION * 2;
+    }
+    assert!(check_for_timeout(&mut app_state));
+    assert_eq!(take(), vec![Cue::Side]);
+    sound::set_backend(None);
+}
+
+#[test]
+fn test_sound_files() {
+    let _lock = setup_test_config();
+    let dir = std::env::temp_dir().join(format!("superhat-sounds-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let tone = sound::wav::tone(440.0, Duration::from_millis(10));
+    assert!(sound::wav::check(&tone).is_ok());
+    std::fs::write(dir.join("osb.wav"), &tone).unwrap();
+    std::fs::write(dir.join("side.wav"), "not a wav").unwrap();
+
+    // Half volume halves every sample, and leaves the header alone
+    let quiet = sound::wav::scale(&tone, 0.5);
+    assert_eq!(quiet[..44], tone[..44]);
+    let sample = |wav: &[u8], index: usize| i16::from_le_bytes([wav[44 + index * 2], wav[45 + index * 2]]);
+    assert_eq!(sample(&quiet, 100), (sample(&tone, 100) as f32 * 0.5) as i16);
+
+    let mut settings = sound::SoundSettings::default();
+    settings.osb.file = Some("osb.wav".into());
+    settings.side.file = Some("side.wav".into());
+    settings.moved.file = Some("missing.wav".into());
+    let problems = sound::load(&settings, &dir);
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert!(problems[0].ends_with("side.wav: Not a WAV file"));
+    assert!(problems[1].starts_with("Couldn't read"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// The portable backend decodes the same files Windows plays
+#[cfg(not(windows))]
+#[test]
+fn test_click_sounds_decode() {
+    use rodio::Source;
+    for wav in [sound::CLICK_LEFT_SOUND, sound::CLICK_RIGHT_SOUND] {
+        let decoder = rodio::Decoder::new(std::io::Cursor::new(wav)).unwrap();
+        assert!(decoder.sample_rate() > 0);
+    }
+}
+
+#[test]
+fn test_voice_pack() {
+    let _lock = setup_test_config();
+    let dir = std::env::temp_dir().join(format!("superhat-voice-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for word in ["left", "top", "three"] {
+        std::fs::write(dir.join(format!("{}.wav", word)), sound::wav::tone(440.0, Duration::from_millis(10))).unwrap();
+    }
+    std::fs::write(dir.join("four.wav"), "not a wav").unwrap();
+
+    let mut settings = sound::SoundSettings::default();
+    settings.voice.pack = Some(dir.join("missing"));
+    assert_eq!(sound::load(&settings, &dir), vec![format!("There's no voice pack at {}", dir.join("missing").display())]);
+    settings.voice.pack = Some(dir.clone());
+    let problems = sound::load(&settings, &dir);
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert!(problems[0].ends_with("four.wav: Not a WAV file"));
+    std::fs::remove_file(dir.join("four.wav")).unwrap();
+
+    // Words the pack has stand in for their cues; the rest still get the cue
+    let (cues, words) = record_sounds(&settings);
+    let mut app_state = AppState::WaitingForSide { mfd: MfdState::RightMfd };
+    select_mfd(Direction::Left, &mut app_state);
+    handle_short_press(Direction::Up, &mut app_state);
+    fire_osb(MfdState::LeftMfd, 3);
+    fire_osb(MfdState::RightMfd, 23);
+    fire_osb(MfdState::LeftMfd, 14);
+    select_mfd(Direction::Right, &mut app_state);
+    assert_eq!(*words.lock().unwrap(), vec!["left", "top", "three", "three"]);
+    assert_eq!(*cues.lock().unwrap(), vec![Cue::Osb, Cue::MfdRight]);
+
+    // Turning a cue off silences its words as well
+    settings.osb.enabled = false;
+    let (cues, words) = record_sounds(&settings);
+    fire_osb(MfdState::LeftMfd, 3);
+    select_mfd(Direction::Left, &mut app_state);
+    assert_eq!(*words.lock().unwrap(), vec!["left"]);
+    assert!(cues.lock().unwrap().is_empty());
+
+    output::release_all();
+    sound::set_backend(None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -5,14 +5,22 @@ use crossterm::{
     event,
 };
 use std::io::{self, Write};
+#[cfg(windows)]
 use windows::Win32::Foundation::HANDLE;
+#[cfg(windows)]
 use windows::Win32::System::Console::{
     GetStdHandle, SetConsoleScreenBufferSize, SetConsoleWindowInfo,
     STD_OUTPUT_HANDLE, COORD, SMALL_RECT,
 };
 
 use crate::{AppState, Direction, MfdState};
-use crate::winstance::WindowInstance;
+use crate::config::{CONFIG, DEFAULT_PROFILE, selection_mode};
+use crate::osb::{self, Selection};
+use crate::history::{self, HISTORY_LENGTH};
+use crate::rocker::{self, ROCKERS};
+use crate::keypad::{KEYPAD_KEYS, KEYPAD_COLUMNS};
+use crate::binding::BindStep;
+use crate::log;
 
 const TOP_LEFT: &str = "┌";
 const TOP_RIGHT: &str = "┐";
@@ -28,18 +36,15 @@ struct ButtonPosition {
     y: u16,
 }
 
-// Add this new helper struct to track which button is currently highlighted
-#[derive(Debug)]
-struct HighlightedButton {
-    position: u8,  // 0-4 representing position on the side
-    side: Direction,
-}
-
 pub struct Ui {
     stdout: io::Stdout,
-    // Keeps the window instance alive to maintain the mutex lock
-    #[allow(dead_code)]
-    window: WindowInstance,
+    keypad_visible: bool,  // The keypad is drawn over an MFD, so that MFD is cleared when it goes
+    extra_hats: Vec<HatView>,
+    message: Option<(String, bool)>,  // Text and whether it's an error, like a config that didn't load
+    headless: bool,
+    logged_status: String,  // Headless only, so each change of state is logged once
+    key_releases: bool,     // Whether the terminal tells us when a key is let go
+    enhanced_keyboard: bool,  // We asked the terminal for key releases and have to undo it
 }
 
 const CONSOLE_WIDTH: u16 = 96;
@@ -48,10 +53,31 @@ const CONSOLE_HEIGHT: u16 = 26;
 #[derive(Debug)]
 struct MfdDisplay {
     active_side: Option<Direction>,
-    highlighted_button: Option<HighlightedButton>,
+    highlighted_button: Option<u8>,  // 0-19, clockwise from the top left
     pressed_osb: Option<u8>,
 }
 
+impl MfdDisplay {
+    // Another hat working on the same MFD shows through wherever this one has nothing to show
+    fn merge(&mut self, other: &MfdDisplay) {
+        self.active_side = self.active_side.or(other.active_side);
+        self.highlighted_button = self.highlighted_button.or(other.highlighted_button);
+        self.pressed_osb = self.pressed_osb.or(other.pressed_osb);
+    }
+}
+
+// (MFD it's drawn over, cursor position, pressed)
+type KeypadView = (MfdState, u8, bool);
+
+// What an extra hat has on screen, drawn on top of the main hat
+#[derive(Debug)]
+struct HatView {
+    left: MfdDisplay,
+    right: MfdDisplay,
+    keypad: Option<KeypadView>,
+    status: String,
+}
+
 // Add these constants near the top with the other UI constants
 const BIND_TEXT_X: u16 = CONSOLE_WIDTH - 11;
 const BIND_TEXT_Y: u16 = CONSOLE_HEIGHT - 2;
@@ -64,9 +90,29 @@ const SOUND_TEXT_Y: u16 = CONSOLE_HEIGHT - 2;
 const SOUND_ON_TEXT: &str = "[SOUND:ON]";
 const SOUND_OFF_TEXT: &str = "[SOUND:OFF]";
 
+// Under the sound button; clicking it moves on to the next profile
+const PROFILE_TEXT_X: u16 = 2;
+const PROFILE_TEXT_Y: u16 = CONSOLE_HEIGHT - 1;
+const PROFILE_TEXT_WIDTH: u16 = 40;
+
+// Top left corner of each MFD's buttons
+const LEFT_MFD_X: u16 = 3;
+const RIGHT_MFD_X: u16 = 51;
+const MFD_Y: u16 = 1;
+
+// Recent OSB list drawn inside each MFD, relative to its corner
+const HISTORY_X: u16 = 16;
+const HISTORY_Y: u16 = 6;
+const HISTORY_WIDTH: u16 = 8;
+
+// ICP keypad, drawn inside the MFD it was opened from
+const KEYPAD_X: u16 = 9;
+const KEYPAD_Y: u16 = 4;
+
 impl Ui {
     pub fn new() -> io::Result<Self> {
-        let window = WindowInstance::new("Superhat")?;
+        #[cfg(windows)]
+        crate::winstance::set_up_window(crate::instance::INSTANCE_NAME);
 
         // Set console size before initializing
         set_console_size(CONSOLE_WIDTH as i16, CONSOLE_HEIGHT as i16);
@@ -79,99 +125,138 @@ impl Ui {
             terminal::EnterAlternateScreen,
             event::EnableMouseCapture
         )?;
-        
-        let mut ui = Ui { stdout, window };
+
+        // Windows consoles report key releases anyway; other terminals have to be asked, if they can
+        let enhanced_keyboard = !cfg!(windows) && terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced_keyboard {
+            crossterm::execute!(
+                stdout,
+                event::PushKeyboardEnhancementFlags(event::KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        let mut ui = Ui {
+            stdout,
+            keypad_visible: false,
+            extra_hats: Vec::new(),
+            message: None,
+            headless: false,
+            logged_status: String::new(),
+            key_releases: cfg!(windows) || enhanced_keyboard,
+            enhanced_keyboard,
+        };
         ui.stdout.queue(cursor::Hide)?;
         ui.stdout.flush()?;
 
         Ok(ui)
     }
 
+    // No terminal at all: nothing is drawn, and messages and state changes are logged instead
+    pub fn headless() -> Self {
+        Ui {
+            stdout: io::stdout(),
+            keypad_visible: false,
+            extra_hats: Vec::new(),
+            message: None,
+            headless: true,
+            logged_status: String::new(),
+            key_releases: false,
+            enhanced_keyboard: false,
+        }
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.headless
+    }
+
+    // Without releases a key can only ever be tapped
+    pub fn reports_key_releases(&self) -> bool {
+        self.key_releases
+    }
+
     pub fn clear(&mut self) -> io::Result<()> {
+        if self.headless {
+            return Ok(());
+        }
         self.stdout.queue(terminal::Clear(terminal::ClearType::All))?;
         self.stdout.flush()?;
         Ok(())
     }
 
+    // Extra hats keep their own state in main, so the UI holds on to what they last looked like
+    pub fn set_extra_hats<'a>(&mut self, states: impl Iterator<Item = &'a AppState>) {
+        self.extra_hats = states.map(|state| {
+            let (left, right) = mfd_displays(state);
+            HatView { left, right, keypad: keypad_view(state), status: status_text(state) }
+        }).collect();
+    }
+
+    pub fn set_message(&mut self, message: String, error: bool) {
+        if self.headless {
+            log::log(&message);
+        }
+        self.message = Some((message, error));
+    }
+
     pub fn update(&mut self, app_state: &AppState) -> io::Result<()> {
-        self.stdout.queue(cursor::MoveTo(0, 0))?;
-        
-        // Convert app state into display state
-        let (left_mfd, right_mfd) = match app_state {
-            AppState::WaitingForSide { mfd } => match mfd {
-                MfdState::LeftMfd => (
-                    MfdDisplay { active_side: Some(Direction::Up), highlighted_button: None, pressed_osb: None },
-                    MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None }
-                ),
-                MfdState::RightMfd => (
-                    MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None },
-                    MfdDisplay { active_side: Some(Direction::Up), highlighted_button: None, pressed_osb: None }
-                ),
-            },
-            AppState::SelectingOSB { mfd, side, inputs, .. } => {
-                let highlighted = if inputs.is_empty() {
-                    Some(HighlightedButton { position: 2, side: *side })
-                } else if let Some(last_input) = inputs.last() {
-                    let (left_dir, right_dir) = get_relative_directions(*side);
-                    let position = match last_input {
-                        d if *d == left_dir => 1,
-                        d if *d == right_dir => 3,
-                        d if *d == *side => 2,
-                        _ => 2,
-                    };
-                    Some(HighlightedButton { position, side: *side })
-                } else {
-                    None
-                };
+        if self.headless {
+            let status = status_text(app_state);
+            if status != self.logged_status {
+                log::log(&status);
+                self.logged_status = status;
+            }
+            return Ok(());
+        }
 
-                match mfd {
-                    MfdState::LeftMfd => (
-                        MfdDisplay { active_side: Some(*side), highlighted_button: highlighted, pressed_osb: None },
-                        MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None }
-                    ),
-                    MfdState::RightMfd => (
-                        MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None },
-                        MfdDisplay { active_side: Some(*side), highlighted_button: highlighted, pressed_osb: None }
-                    ),
-                }
-            },
-            AppState::OSBPressed { mfd, osb_number } => match mfd {
-                MfdState::LeftMfd => (
-                    MfdDisplay { active_side: Some(Direction::Up), highlighted_button: None, pressed_osb: Some(*osb_number) },
-                    MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None }
-                ),
-                MfdState::RightMfd => (
-                    MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None },
-                    MfdDisplay { active_side: Some(Direction::Up), highlighted_button: None, pressed_osb: Some(*osb_number) }
-                ),
-            },
-            _ => (
-                MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None },
-                MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None }
-            ),
-        };
+        let (mut left_mfd, mut right_mfd) = mfd_displays(app_state);
+        let mut keypad = keypad_view(app_state);
+        for hat in &self.extra_hats {
+            left_mfd.merge(&hat.left);
+            right_mfd.merge(&hat.right);
+            keypad = keypad.or_else(|| hat.keypad.clone());
+        }
+
+        if keypad.is_some() != self.keypad_visible {
+            self.keypad_visible = keypad.is_some();
+            self.clear()?;
+        }
+
+        self.stdout.queue(cursor::MoveTo(0, 0))?;
 
         // Render both MFDs
-        self.render_mfd(3, 1, &left_mfd, false)?;
-        self.render_mfd(51, 1, &right_mfd, true)?;
+        self.render_mfd(LEFT_MFD_X, MFD_Y, &left_mfd, false)?;
+        self.render_mfd(RIGHT_MFD_X, MFD_Y, &right_mfd, true)?;
+        self.render_rockers(LEFT_MFD_X, MFD_Y, &MfdState::LeftMfd, app_state)?;
+        self.render_rockers(RIGHT_MFD_X, MFD_Y, &MfdState::RightMfd, app_state)?;
+        for (start_x, mfd) in [(LEFT_MFD_X, MfdState::LeftMfd), (RIGHT_MFD_X, MfdState::RightMfd)] {
+            match &keypad {
+                Some((keypad_mfd, position, pressed)) if *keypad_mfd == mfd => {
+                    self.render_keypad(start_x, MFD_Y, *position, *pressed)?;
+                }
+                _ => self.render_history(start_x, MFD_Y, &mfd)?,
+            }
+        }
 
         // Render status line
         self.render_status_line(app_state)?;
+        self.render_extra_hats_line(app_state)?;
+        self.render_message_line()?;
 
         // Draw the bind/cancel button depending on state
         match app_state {
-            AppState::BindingMode { .. } => self.draw_cancel_button()?,
+            AppState::Binding(_) => self.draw_cancel_button()?,
             _ => self.draw_bind_button()?,
         }
 
         // Draw the sound button (add this before the final flush)
         self.draw_sound_button()?;
+        self.draw_profile_button()?;
 
         self.stdout.flush()?;
         Ok(())
     }
 
-    fn draw_button(&mut self, number: u8, pos: ButtonPosition, highlighted: bool, active: bool, pressed: bool) -> io::Result<()> {
+    fn draw_button(&mut self, label: &str, pos: ButtonPosition, highlighted: bool, active: bool, pressed: bool) -> io::Result<()> {
         // Helper to get the character style based on button state
         fn get_colors(pressed: bool, highlighted: bool, active: bool) -> (Color, Option<Color>) {
             match (pressed, highlighted, active) {
@@ -183,7 +268,7 @@ impl Ui {
         }
 
         // Helper to get the character at a specific position
-        fn get_char(dx: u16, dy: u16, number: u8) -> String {
+        fn get_char(dx: u16, dy: u16, label: &str) -> String {
             match (dx, dy) {
                 (0, 0) => TOP_LEFT.to_string(),
                 (5, 0) => TOP_RIGHT.to_string(),
@@ -191,8 +276,7 @@ impl Ui {
                 (5, 2) => BOTTOM_RIGHT.to_string(),
                 (_, 0) | (_, 2) => HORIZONTAL.to_string(),
                 (0, _) | (5, _) => VERTICAL.to_string(),
-                (2, 1) => format!("{:02}", number).chars().nth(0).unwrap().to_string(),
-                (3, 1) => format!("{:02}", number).chars().nth(1).unwrap().to_string(),
+                (1..=4, 1) => format!("{:^4}", label).chars().nth(dx as usize - 1).unwrap().to_string(),
                 _ => " ".to_string()
             }
         }
@@ -202,7 +286,7 @@ impl Ui {
             self.stdout.queue(cursor::MoveTo(pos.x, pos.y + dy))?;
             
             for dx in 0..6 {
-                let char = get_char(dx, dy, number);
+                let char = get_char(dx, dy, label);
                 let (fg_color, bg_color) = get_colors(pressed, highlighted, active);
                 
                 let styled = match bg_color {
@@ -224,6 +308,8 @@ impl Ui {
         is_right_mfd: bool,
     ) -> io::Result<()> {
         let base_number = if is_right_mfd { 20 } else { 0 };
+        // Labels from the profile stand in for the numbers
+        let key_profile = CONFIG.lock().unwrap().as_ref().map(|config| config.key_profile().clone()).unwrap_or_default();
         
         for (i, (rel_x, rel_y)) in BUTTON_POSITIONS.iter().enumerate() {
             let button_num = (i as u8) + 1;
@@ -232,29 +318,16 @@ impl Ui {
                 y: start_y + rel_y,
             };
 
-            let is_highlighted = display.highlighted_button.as_ref().map_or(false, |hb| {
-                let button_side = match i {
-                    0..=4 => Direction::Up,
-                    5..=9 => Direction::Right,
-                    10..=14 => Direction::Down,
-                    _ => Direction::Left,
-                };
-                
-                let position_in_side = match i {
-                    0..=4 => i,
-                    5..=9 => i - 5,
-                    10..=14 => i - 10,
-                    _ => i - 15,
-                };
-
-                hb.side == button_side && position_in_side as u8 == hb.position
-            });
-
-            let is_pressed = display.pressed_osb.map_or(false, |osb| osb == button_num + base_number);
+            let is_highlighted = display.highlighted_button == Some(i as u8);
+            let is_pressed = display.pressed_osb == Some(button_num + base_number);
             let is_active = display.active_side.is_some();
 
+            let label = match key_profile.osb_label(button_num + base_number) {
+                Some(label) => label.chars().take(4).collect(),
+                None => format!("{:02}", button_num + base_number),
+            };
             self.draw_button(
-                button_num + base_number,
+                &label,
                 pos,
                 is_highlighted,
                 is_active,
@@ -264,35 +337,76 @@ impl Ui {
         Ok(())
     }
 
+    fn render_rockers(&mut self, start_x: u16, start_y: u16, mfd: &MfdState, app_state: &AppState) -> io::Result<()> {
+        for (rocker, (rel_x, rel_y)) in ROCKERS.iter().zip(ROCKER_POSITIONS.iter()) {
+            let (active, highlighted, pressed) = match app_state {
+                AppState::SelectingRocker { mfd: selected, inputs, .. } if selected == mfd => {
+                    // Light up every corner the presses so far could still lead to
+                    (true, !inputs.is_empty() && rocker::could_reach(inputs, *rocker), false)
+                }
+                AppState::RockerPressed { mfd: selected, rocker: pressed, .. } if selected == mfd => {
+                    (true, false, pressed == rocker)
+                }
+                _ => (false, false, false),
+            };
+
+            let pos = ButtonPosition {
+                x: start_x + rel_x,
+                y: start_y + rel_y,
+            };
+            self.draw_button(rocker.label(), pos, highlighted, active, pressed)?;
+        }
+        Ok(())
+    }
+
+    fn render_keypad(&mut self, start_x: u16, start_y: u16, position: u8, pressed: bool) -> io::Result<()> {
+        for (i, key) in KEYPAD_KEYS.iter().enumerate() {
+            let pos = ButtonPosition {
+                x: start_x + KEYPAD_X + (i as u16 % KEYPAD_COLUMNS as u16) * 6,
+                y: start_y + KEYPAD_Y + (i as u16 / KEYPAD_COLUMNS as u16) * 3,
+            };
+            let selected = i as u8 == position;
+            self.draw_button(key.label(), pos, selected && !pressed, true, selected && pressed)?;
+        }
+        Ok(())
+    }
+
+    fn render_history(&mut self, start_x: u16, start_y: u16, mfd: &MfdState) -> io::Result<()> {
+        let recent = history::recent(mfd);
+
+        self.stdout.queue(cursor::MoveTo(start_x + HISTORY_X, start_y + HISTORY_Y))?;
+        let title = if recent.is_empty() { "" } else { "RECENT" };
+        write!(self.stdout, "{}", style::style(format!("{:^width$}", title, width = HISTORY_WIDTH as usize)).with(Color::DarkGrey))?;
+
+        for row in 0..HISTORY_LENGTH {
+            self.stdout.queue(cursor::MoveTo(start_x + HISTORY_X, start_y + HISTORY_Y + 1 + row as u16))?;
+            let entry = recent.get(row).map(|osb| format!("OSB {}", osb)).unwrap_or_default();
+            write!(self.stdout, "{}", style::style(format!("{:^width$}", entry, width = HISTORY_WIDTH as usize)).with(Color::Grey))?;
+        }
+        Ok(())
+    }
+
+    // Which history entry, if any, is under the mouse
+    pub fn history_entry_at(&self, x: u16, y: u16) -> Option<(MfdState, u8)> {
+        let mfd = if (LEFT_MFD_X + HISTORY_X..LEFT_MFD_X + HISTORY_X + HISTORY_WIDTH).contains(&x) {
+            MfdState::LeftMfd
+        } else if (RIGHT_MFD_X + HISTORY_X..RIGHT_MFD_X + HISTORY_X + HISTORY_WIDTH).contains(&x) {
+            MfdState::RightMfd
+        } else {
+            return None;
+        };
+
+        let row = y.checked_sub(MFD_Y + HISTORY_Y + 1)? as usize;
+        let osb = *history::recent(&mfd).get(row)?;
+        Some((mfd, osb))
+    }
+
     fn render_status_line(&mut self, app_state: &AppState) -> io::Result<()> {
         let status_line_y = CONSOLE_HEIGHT - 2;
         self.stdout.queue(cursor::MoveTo(0, status_line_y))?;
         self.stdout.queue(terminal::Clear(terminal::ClearType::CurrentLine))?;
 
-        // Get the status message based on app state
-        let status = match app_state {
-            AppState::WaitingForSide { mfd } => {
-                format!("{} MFD SELECTED", 
-                    if matches!(mfd, MfdState::LeftMfd) { "LEFT" } else { "RIGHT" })
-            }
-            AppState::SelectingOSB { mfd, side, .. } => {
-                format!("Selecting OSB on {} MFD, {} side", 
-                    if matches!(mfd, MfdState::LeftMfd) { "LEFT" } else { "RIGHT" },
-                    format!("{:?}", side).to_uppercase())
-            }
-            AppState::OSBPressed { mfd, osb_number } => {
-                format!("OSB {} pressed on {} MFD", 
-                    osb_number,
-                    if matches!(mfd, MfdState::LeftMfd) { "LEFT" } else { "RIGHT" })
-            }
-            AppState::InvalidSequence { .. } => {
-                // This is actually a waiting state, so we don't need to show anything
-                "".to_string()
-            }
-            AppState::BindingMode { waiting_for } => {
-                format!("Binding mode: Press button for {:?}", waiting_for)
-            }
-        };
+        let status = status_text(app_state);
 
         // Calculate padding for centering
         let padding = (CONSOLE_WIDTH as usize - status.len()) / 2;
@@ -302,6 +416,42 @@ impl Ui {
         Ok(())
     }
 
+    fn render_message_line(&mut self) -> io::Result<()> {
+        let line_y = CONSOLE_HEIGHT - 4;
+        self.stdout.queue(cursor::MoveTo(0, line_y))?;
+        self.stdout.queue(terminal::Clear(terminal::ClearType::CurrentLine))?;
+
+        let Some((message, error)) = &self.message else { return Ok(()) };
+        let color = if *error { Color::Red } else { Color::Green };
+        let message: String = message.chars().take(CONSOLE_WIDTH as usize).collect();
+        let padding = (CONSOLE_WIDTH as usize - message.chars().count()) / 2;
+        self.stdout.queue(cursor::MoveTo(padding as u16, line_y))?;
+        write!(self.stdout, "{}", style::style(message).with(color))?;
+        Ok(())
+    }
+
+    // Shared with the binding wizard, which has more to say than fits on the status line
+    fn render_extra_hats_line(&mut self, app_state: &AppState) -> io::Result<()> {
+        let line_y = CONSOLE_HEIGHT - 3;
+        self.stdout.queue(cursor::MoveTo(0, line_y))?;
+        self.stdout.queue(terminal::Clear(terminal::ClearType::CurrentLine))?;
+
+        // The main hat is hat 1
+        let status = match binding_detail(app_state) {
+            Some(detail) => detail,
+            None => self.extra_hats.iter().enumerate()
+                .map(|(i, hat)| format!("HAT {}: {}", i + 2, hat.status))
+                .collect::<Vec<_>>()
+                .join(" | "),
+        };
+        let status: String = status.chars().take(CONSOLE_WIDTH as usize).collect();
+
+        let padding = (CONSOLE_WIDTH as usize - status.chars().count()) / 2;
+        self.stdout.queue(cursor::MoveTo(padding as u16, line_y))?;
+        write!(self.stdout, "{}", style::style(status).with(Color::Grey))?;
+        Ok(())
+    }
+
     pub fn handle_resize(&mut self, width: u16, height: u16, app_state: &AppState) -> io::Result<()> {
         // Force it back
         if width == CONSOLE_WIDTH && height == CONSOLE_HEIGHT { 
@@ -348,6 +498,20 @@ impl Ui {
         Ok(())
     }
 
+    fn draw_profile_button(&mut self) -> io::Result<()> {
+        self.stdout.queue(cursor::MoveTo(PROFILE_TEXT_X, PROFILE_TEXT_Y))?;
+        self.stdout.queue(terminal::Clear(terminal::ClearType::CurrentLine))?;
+        let name = CONFIG.lock().unwrap().as_ref()
+            .map_or(DEFAULT_PROFILE.to_string(), |config| config.profile_name().to_string());
+        let text: String = format!("[PROFILE:{}]", name).chars().take(PROFILE_TEXT_WIDTH as usize).collect();
+        write!(self.stdout, "{}", style::style(text).with(Color::Grey))?;
+        Ok(())
+    }
+
+    pub fn is_profile_button_click(&self, x: u16, y: u16) -> bool {
+        (PROFILE_TEXT_X..PROFILE_TEXT_X + PROFILE_TEXT_WIDTH).contains(&x) && y == PROFILE_TEXT_Y
+    }
+
     pub fn is_sound_button_click(&self, x: u16, y: u16) -> bool {
         x >= SOUND_TEXT_X && 
         x < SOUND_TEXT_X + SOUND_ON_TEXT.len() as u16 && 
@@ -357,6 +521,12 @@ impl Ui {
 
 impl Drop for Ui {
     fn drop(&mut self) {
+        if self.headless {
+            return;
+        }
+        if self.enhanced_keyboard {
+            let _ = crossterm::execute!(self.stdout, event::PopKeyboardEnhancementFlags);
+        }
         let _ = terminal::disable_raw_mode();
         let _ = crossterm::execute!(
             self.stdout,
@@ -366,6 +536,7 @@ impl Drop for Ui {
     }
 }
 
+#[cfg(windows)]
 fn set_console_size(width: i16, height: i16) {
     unsafe {
         let handle = GetStdHandle(STD_OUTPUT_HANDLE).expect("Failed to get console handle");
@@ -391,13 +562,141 @@ fn set_console_size(width: i16, height: i16) {
     }
 }
 
-// Helper function to get relative directions (if not already defined)
-fn get_relative_directions(side: Direction) -> (Direction, Direction) {
-    match side {
-        Direction::Up => (Direction::Left, Direction::Right),
-        Direction::Right => (Direction::Up, Direction::Down),
-        Direction::Down => (Direction::Right, Direction::Left),
-        Direction::Left => (Direction::Down, Direction::Up),
+// Convert app state into display state
+fn mfd_displays(app_state: &AppState) -> (MfdDisplay, MfdDisplay) {
+    match app_state {
+        AppState::WaitingForSide { mfd } => match mfd {
+            MfdState::LeftMfd => (
+                MfdDisplay { active_side: Some(Direction::Up), highlighted_button: None, pressed_osb: None },
+                MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None }
+            ),
+            MfdState::RightMfd => (
+                MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None },
+                MfdDisplay { active_side: Some(Direction::Up), highlighted_button: None, pressed_osb: None }
+            ),
+        },
+        AppState::SelectingOSB { mfd, side, inputs, .. } => {
+            // Ask the active strategy so the highlight always matches the logic
+            let highlighted = match osb::strategy(selection_mode()).select(*side, inputs) {
+                Selection::Partial(index) => index,
+                _ => None,
+            };
+
+            match mfd {
+                MfdState::LeftMfd => (
+                    MfdDisplay { active_side: Some(*side), highlighted_button: highlighted, pressed_osb: None },
+                    MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None }
+                ),
+                MfdState::RightMfd => (
+                    MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None },
+                    MfdDisplay { active_side: Some(*side), highlighted_button: highlighted, pressed_osb: None }
+                ),
+            }
+        },
+        // Shown on the MFD the OSB is on, which isn't the selected one when it comes from the history
+        AppState::OSBPressed { osb_number, .. } => match MfdState::of_osb(*osb_number) {
+            MfdState::LeftMfd => (
+                MfdDisplay { active_side: Some(Direction::Up), highlighted_button: None, pressed_osb: Some(*osb_number) },
+                MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None }
+            ),
+            MfdState::RightMfd => (
+                MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None },
+                MfdDisplay { active_side: Some(Direction::Up), highlighted_button: None, pressed_osb: Some(*osb_number) }
+            ),
+        },
+        AppState::CursorRoaming { mfd, position } => match mfd {
+            MfdState::LeftMfd => (
+                MfdDisplay { active_side: Some(Direction::Up), highlighted_button: Some(*position), pressed_osb: None },
+                MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None }
+            ),
+            MfdState::RightMfd => (
+                MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None },
+                MfdDisplay { active_side: Some(Direction::Up), highlighted_button: Some(*position), pressed_osb: None }
+            ),
+        },
+        _ => (
+            MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None },
+            MfdDisplay { active_side: None, highlighted_button: None, pressed_osb: None }
+        ),
+    }
+}
+
+fn keypad_view(app_state: &AppState) -> Option<KeypadView> {
+    match app_state {
+        AppState::Keypad { mfd, position, pressed, .. } => Some((mfd.clone(), *position, *pressed)),
+        _ => None,
+    }
+}
+
+// What the binding wizard has detected or turned down, with the keys for getting around it
+fn binding_detail(app_state: &AppState) -> Option<String> {
+    let AppState::Binding(wizard) = app_state else { return None };
+    let detail = match (&wizard.message, &wizard.pending) {
+        (Some(message), _) => message.clone(),
+        (None, Some((_, label))) => format!("Detected: {}", label),
+        (None, None) => String::new(),
+    };
+    let keys = "Backspace: back  Esc: cancel";
+    Some(if detail.is_empty() { keys.to_string() } else { format!("{}  |  {}", detail, keys) })
+}
+
+// Get the status message based on app state
+pub fn status_text(app_state: &AppState) -> String {
+    match app_state {
+        AppState::WaitingForSide { mfd } => {
+            format!("{} MFD SELECTED", 
+                if matches!(mfd, MfdState::LeftMfd) { "LEFT" } else { "RIGHT" })
+        }
+        AppState::SelectingOSB { mfd, side, .. } => {
+            format!("Selecting OSB on {} MFD, {} side", 
+                if matches!(mfd, MfdState::LeftMfd) { "LEFT" } else { "RIGHT" },
+                format!("{:?}", side).to_uppercase())
+        }
+        AppState::OSBPressed { osb_number, .. } => {
+            format!("OSB {} pressed on {} MFD", 
+                osb_number,
+                if matches!(MfdState::of_osb(*osb_number), MfdState::LeftMfd) { "LEFT" } else { "RIGHT" })
+        }
+        AppState::InvalidSequence { .. } => {
+            // This is actually a waiting state, so we don't need to show anything
+            "".to_string()
+        }
+        AppState::CursorRoaming { mfd, position } => {
+            format!("Cursor on OSB {} on {} MFD",
+                position + 1,
+                if matches!(mfd, MfdState::LeftMfd) { "LEFT" } else { "RIGHT" })
+        }
+        AppState::SelectingRocker { mfd, .. } => {
+            format!("Selecting rocker on {} MFD",
+                if matches!(mfd, MfdState::LeftMfd) { "LEFT" } else { "RIGHT" })
+        }
+        AppState::RockerPressed { mfd, rocker, up } => {
+            format!("{} {} on {} MFD",
+                rocker.label(),
+                if *up { "UP" } else { "DOWN" },
+                if matches!(mfd, MfdState::LeftMfd) { "LEFT" } else { "RIGHT" })
+        }
+        AppState::Keypad { position, pressed, .. } => {
+            format!("ICP {} {}",
+                KEYPAD_KEYS[*position as usize].label(),
+                if *pressed { "pressed" } else { "selected" })
+        }
+        AppState::MacroLayer { pressed, .. } => match pressed {
+            Some(direction) => format!("Macro {} pressed", format!("{:?}", direction).to_uppercase()),
+            None => "Macro layer".to_string(),
+        },
+        AppState::Binding(wizard) => {
+            let step = wizard.step();
+            let (number, total) = wizard.progress();
+            let prompt = match (&wizard.pending, step) {
+                (Some(_), _) => "press it again to confirm".to_string(),
+                (None, BindStep::Confirm) => "press a button, or a hat direction for long press".to_string(),
+                (None, BindStep::Modifier) => "press a button, or a hat direction to skip".to_string(),
+                (None, _) => "press a button or key".to_string(),
+            };
+            format!("Bind {} ({}/{}): {}", step.label(), number, total, prompt)
+        }
+        AppState::Learning { next, .. } => format!("Learn {}: press a pad or button, or Esc to stop", next),
     }
 }
 
@@ -411,4 +710,13 @@ const BUTTON_POSITIONS: [(u16, u16); 20] = [
     (30, 18), (24, 18), (18, 18), (12, 18), (6, 18),
     // Left side (16-20) - reversed order
     (0, 15), (0, 12), (0, 9), (0, 6), (0, 3),
-]; 
\ No newline at end of file
+]; 
+
+// Corner rockers, in the same order as ROCKERS
+const ROCKER_POSITIONS: [(u16, u16); 4] = [
+    (0, 0), (36, 0), (36, 18), (0, 18),
+];
+
+// Other terminals keep whatever size the user gave them
+#[cfg(not(windows))]
+fn set_console_size(_width: i16, _height: i16) {}